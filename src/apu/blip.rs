@@ -0,0 +1,153 @@
+use std::f64::consts::PI;
+
+/// Kernel phases per output sample
+const PHASES: usize = 64;
+/// Kernel width in output samples
+const TAPS: usize = 16;
+/// Kernel cutoff as a fraction of the output Nyquist frequency
+const CUTOFF: f64 = 0.9;
+/// Output samples that can be waiting to be read
+const BUFFER_SIZE: usize = 0x4000;
+
+/// Band-limited step synthesis. Input is a list of amplitude changes at
+/// clock times, each one is drawn as a band-limited step into the output
+/// sample stream, so resampling doesn't alias
+pub struct BlipBuf {
+    /// Output samples per input clock
+    factor: f64,
+    /// Output position of the current frame's first clock
+    offset: f64,
+    /// Sample deltas, summed as they're read
+    buf: Vec<f32>,
+    /// Running sum of the deltas read so far
+    integrator: f32,
+    /// Step deltas for each fractional sample position
+    kernel: Vec<[f32; TAPS]>,
+}
+
+impl BlipBuf {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        Self {
+            factor: sample_rate / clock_rate,
+            offset: 0.0,
+            buf: vec![0.0; BUFFER_SIZE + TAPS],
+            integrator: 0.0,
+            kernel: (0..PHASES).map(kernel_phase).collect(),
+        }
+    }
+
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.factor = sample_rate / clock_rate;
+    }
+
+    // Amplitude change at a clock within the current frame
+    pub fn add_delta(&mut self, clock: u32, delta: f32) {
+        let pos = self.offset + f64::from(clock) * self.factor;
+        let index = pos as usize;
+        if index >= BUFFER_SIZE {
+            return;
+        }
+        let phase = ((pos - index as f64) * PHASES as f64) as usize;
+        for (out, k) in self.buf[index..index + TAPS]
+            .iter_mut()
+            .zip(&self.kernel[phase])
+        {
+            *out += delta * k;
+        }
+    }
+
+    // Close a frame of the given length in clocks, its samples become
+    // readable. Samples that overflow the buffer are dropped
+    pub fn end_frame(&mut self, clocks: u32) {
+        self.offset = (self.offset + f64::from(clocks) * self.factor).min(BUFFER_SIZE as f64);
+    }
+
+    pub fn samples_avail(&self) -> usize {
+        (self.offset as usize).min(BUFFER_SIZE)
+    }
+
+    // Take every finished sample
+    pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+        let count = self.samples_avail();
+        for delta in &self.buf[..count] {
+            self.integrator += delta;
+            out.push(self.integrator);
+        }
+
+        self.buf.copy_within(count.., 0);
+        let len = self.buf.len();
+        self.buf[len - count..].fill(0.0);
+        self.offset -= count as f64;
+    }
+}
+
+// Differences of a windowed sinc step, starting `phase` / PHASES of a sample
+// late. Each phase sums to 1 so steps land at their exact amplitude
+fn kernel_phase(phase: usize) -> [f32; TAPS] {
+    let center = (TAPS / 2) as f64 - 1.0 + phase as f64 / PHASES as f64;
+    let mut kernel = [0.0; TAPS];
+    for (i, k) in kernel.iter_mut().enumerate() {
+        // Midpoint between this sample and the one before it
+        let x = i as f64 - 0.5 - center;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * CUTOFF * x).sin() / (PI * CUTOFF * x)
+        };
+        // Blackman window over the kernel width
+        let w = 2.0 * PI * (x / TAPS as f64 + 0.5);
+        let window = 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+        *k = sinc * window.max(0.0);
+    }
+
+    let sum: f64 = kernel.iter().sum();
+    kernel.map(|k| (k / sum) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_phases_sum_to_one() {
+        for phase in 0..PHASES {
+            let sum: f32 = kernel_phase(phase).iter().sum();
+            assert!((sum - 1.0).abs() < 1e-5, "phase {phase}: {sum}");
+        }
+    }
+
+    #[test]
+    fn sample_count_carries_fractions() {
+        // 1.5 samples per frame
+        let mut blip = BlipBuf::new(64.0, 4.0);
+        let mut out = Vec::new();
+        for _ in 0..10 {
+            blip.end_frame(24);
+            blip.read_samples(&mut out);
+        }
+        assert_eq!(out.len(), 15);
+
+        // Doubling the sample rate doubles the output
+        blip.set_rates(64.0, 8.0);
+        blip.end_frame(64);
+        blip.read_samples(&mut out);
+        assert_eq!(out.len(), 23);
+    }
+
+    #[test]
+    fn step() {
+        let mut blip = BlipBuf::new(1000.0, 100.0);
+        blip.add_delta(500, 0.5);
+        blip.end_frame(1000);
+        let mut out = Vec::new();
+        blip.read_samples(&mut out);
+        assert_eq!(out.len(), 100);
+
+        // The kernel delays the step by half its width. Flat before it and
+        // settled to its height after, the ringing stays within the kernel
+        assert!(out[..50].iter().all(|s| s.abs() < 1e-6));
+        assert!(out[50 + TAPS..].iter().all(|s| (s - 0.5).abs() < 1e-5));
+        let mid = 50 + TAPS / 2 - 1;
+        assert!(out[mid - 1] < 0.25 && out[mid] >= 0.25);
+    }
+}
@@ -0,0 +1,224 @@
+/// Output unit periods in CPU cycles, NTSC
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// Delta modulation channel, $4010-$4013. Plays 1 bit deltas fetched from
+/// PRG space by DMA
+pub struct Dmc {
+    irq_enabled: bool,
+    pub irq: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    /// 7 bit output level
+    level: u8,
+    /// Sample start, $C000-$FFC0
+    sample_addr: u16,
+    /// Sample length in bytes
+    sample_len: u16,
+    /// Memory reader position
+    addr: u16,
+    remaining: u16,
+    /// Byte waiting for the output unit
+    buffer: Option<u8>,
+    /// A fetch was handed to the bus and hasn't come back
+    fetching: bool,
+    /// Output unit shift register
+    shift: u8,
+    bits: u8,
+    silence: bool,
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Self {
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            period: RATE_TABLE[0],
+            timer: 0,
+            level: 0,
+            sample_addr: 0xc000,
+            sample_len: 1,
+            addr: 0xc000,
+            remaining: 0,
+            buffer: None,
+            fetching: false,
+            shift: 0,
+            bits: 8,
+            silence: true,
+        }
+    }
+
+    // Register write, addr is 0-3
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0 => {
+                self.irq_enabled = val & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = val & 0x40 != 0;
+                self.period = RATE_TABLE[(val & 0x0f) as usize];
+            }
+            1 => self.level = val & 0x7f,
+            2 => self.sample_addr = 0xc000 | (u16::from(val) << 6),
+            _ => self.sample_len = (u16::from(val) << 4) | 1,
+        }
+    }
+
+    // $4015 enable bit. Enabling only starts a sample that has finished
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.remaining = 0;
+        } else if self.remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.addr = self.sample_addr;
+        self.remaining = self.sample_len;
+    }
+
+    pub fn active(&self) -> bool {
+        self.remaining > 0
+    }
+
+    // Address to fetch when the buffer has run dry. The fetch is marked
+    // in flight until fill hands the byte back
+    pub fn request(&mut self) -> Option<u16> {
+        if self.buffer.is_none() && self.remaining > 0 && !self.fetching {
+            self.fetching = true;
+            Some(self.addr)
+        } else {
+            None
+        }
+    }
+
+    // Sample byte from the bus
+    pub fn fill(&mut self, val: u8) {
+        self.fetching = false;
+        self.buffer = Some(val);
+        self.addr = self.addr.checked_add(1).unwrap_or(0x8000);
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // CPU cycle clock
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits -= 1;
+        if self.bits == 0 {
+            self.bits = 8;
+            match self.buffer.take() {
+                Some(val) => {
+                    self.silence = false;
+                    self.shift = val;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_fetches() {
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0x80);
+        dmc.write(2, 0xff);
+        dmc.write(3, 0x04);
+        assert_eq!(dmc.request(), None);
+
+        // 65 bytes from $FFC0, wrapping to $8000 after $FFFF
+        dmc.set_enabled(true);
+        for addr in (0xffc0..=0xffff).chain([0x8000]) {
+            assert!(dmc.active());
+            assert_eq!(dmc.request(), Some(addr));
+            // Nothing more until the byte comes back and is played
+            assert_eq!(dmc.request(), None);
+            dmc.fill(0);
+            assert_eq!(dmc.request(), None);
+            dmc.buffer = None;
+        }
+        assert!(!dmc.active());
+        assert!(dmc.irq);
+        assert_eq!(dmc.request(), None);
+
+        // Enabling clears the IRQ and restarts the finished sample
+        dmc.set_enabled(true);
+        assert!(!dmc.irq);
+        assert_eq!(dmc.request(), Some(0xffc0));
+    }
+
+    #[test]
+    fn looping() {
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0xc0);
+        dmc.write(2, 0x01);
+        dmc.write(3, 0x00);
+        dmc.set_enabled(true);
+        assert_eq!(dmc.request(), Some(0xc040));
+        dmc.fill(0);
+        dmc.buffer = None;
+        assert_eq!(dmc.request(), Some(0xc040));
+        assert!(!dmc.irq);
+    }
+
+    #[test]
+    fn output_unit() {
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0x0f);
+        dmc.write(1, 0x40);
+        dmc.set_enabled(true);
+        dmc.request();
+        dmc.fill(0b0000_0101);
+
+        // The 8 silent bits in progress end, then the byte plays a bit per
+        // period: up 2 for a 1, down 2 for a 0
+        let period = u32::from(RATE_TABLE[0x0f]);
+        let mut levels = Vec::new();
+        for _ in 0..16 * period {
+            dmc.clock_timer();
+            levels.push(dmc.output());
+        }
+        let levels: Vec<u8> = levels.chunks(period as usize).map(|c| c[0]).collect();
+        assert_eq!(levels[..8], [0x40; 8]);
+        assert_eq!(
+            levels[8..],
+            [0x42, 0x40, 0x42, 0x40, 0x3e, 0x3c, 0x3a, 0x38]
+        );
+    }
+}
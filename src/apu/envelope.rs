@@ -0,0 +1,55 @@
+/// Volume envelope shared by the pulse and noise channels
+#[derive(Default)]
+pub struct Envelope {
+    /// Restart on the next quarter frame
+    start: bool,
+    /// Loop the decay, shares its bit with the length counter halt
+    looping: bool,
+    /// Output the volume directly instead of the decay level
+    constant: bool,
+    /// Constant volume, or the divider period
+    volume: u8,
+    divider: u8,
+    /// Decay level, counts down from 15
+    decay: u8,
+}
+
+impl Envelope {
+    // Low 6 bits of $4000/$4004/$400C
+    pub fn write(&mut self, val: u8) {
+        self.looping = val & 0x20 != 0;
+        self.constant = val & 0x10 != 0;
+        self.volume = val & 0x0f;
+    }
+
+    // Length counter load restarts the envelope
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    // Quarter frame clock
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::f32::consts::PI;
+
+/// First order filter, like the RC stages on the console's audio output
+pub struct Filter {
+    high_pass: bool,
+    alpha: f32,
+    prev_in: f32,
+    prev_out: f32,
+}
+
+impl Filter {
+    pub fn high_pass(sample_rate: f32, cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        Self {
+            high_pass: true,
+            alpha: rc / (rc + 1.0 / sample_rate),
+            prev_in: 0.0,
+            prev_out: 0.0,
+        }
+    }
+
+    pub fn low_pass(sample_rate: f32, cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Self {
+            high_pass: false,
+            alpha: dt / (rc + dt),
+            prev_in: 0.0,
+            prev_out: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, val: f32) -> f32 {
+        let out = if self.high_pass {
+            self.alpha * (self.prev_out + val - self.prev_in)
+        } else {
+            self.prev_out + self.alpha * (val - self.prev_out)
+        };
+        self.prev_in = val;
+        self.prev_out = out;
+        out
+    }
+}
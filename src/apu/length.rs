@@ -0,0 +1,44 @@
+/// Lengths loaded by the top 5 bits of the channels' fourth register
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Length counter, silences a channel when it runs out
+#[derive(Default)]
+pub struct LengthCounter {
+    /// Enabled through $4015, a disabled counter is held at 0
+    enabled: bool,
+    /// Stop counting, shares its bit with the envelope loop
+    pub halt: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    // $4015 enable bit
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    // Load from the top 5 bits of a write, ignored while disabled
+    pub fn load(&mut self, val: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(val >> 3) as usize];
+        }
+    }
+
+    // Half frame clock
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
@@ -0,0 +1,385 @@
+mod blip;
+mod dmc;
+mod envelope;
+mod filter;
+mod length;
+mod noise;
+mod pulse;
+mod triangle;
+
+use blip::BlipBuf;
+use dmc::Dmc;
+use filter::Filter;
+use noise::Noise;
+pub(crate) use pulse::Pulse;
+use triangle::Triangle;
+
+/// CPU clock, NTSC
+pub const CLOCK_RATE: f64 = 1_789_773.0;
+/// Cycles between resampler frame ends, so the clock can't run away when
+/// nobody reads samples
+const BLIP_FRAME: u32 = 4096;
+/// Output sample rate until the frontend picks one
+const DEFAULT_SAMPLE_RATE: f64 = 44_100.0;
+
+/// Frame counter steps in CPU cycles, NTSC
+const STEP_1: u32 = 7457;
+const STEP_2: u32 = 14913;
+const STEP_3: u32 = 22371;
+const STEP_4: u32 = 29829;
+/// Last cycle of a 4-step sequence, the IRQ is held from STEP_4 - 1
+const STEP_4_END: u32 = 29830;
+/// Fifth step of the 5-step sequence, which has no IRQ
+const STEP_5: u32 = 37281;
+const STEP_5_END: u32 = 37282;
+
+/// Audio processing unit, $4000-$4013, $4015 and $4017
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    /// CPU cycles into the frame counter sequence
+    frame_cycle: u32,
+    /// 5-step sequence selected
+    five_step: bool,
+    /// Frame IRQ disabled
+    irq_inhibit: bool,
+    frame_irq: bool,
+    /// CPU cycles until a $4017 write resets the sequence
+    frame_reset: Option<u8>,
+    /// The pulse and noise timers run every other CPU cycle
+    odd_cycle: bool,
+    /// Resampler fed with every change of the mixer output
+    blip: BlipBuf,
+    /// CPU cycles since the last end_frame
+    clock: u32,
+    /// Mixer output as of the last delta
+    last_output: f32,
+    sample_rate: f64,
+    /// The console's output stages: two high-passes and a low-pass
+    filters: [Filter; 3],
+    /// Scratch space for resampled, unfiltered samples
+    raw: Vec<f32>,
+}
+
+// High-passes at 90 Hz and 440 Hz and a low-pass at 14 kHz
+fn output_filters(rate: f64) -> [Filter; 3] {
+    let rate = rate as f32;
+    [
+        Filter::high_pass(rate, 90.0),
+        Filter::high_pass(rate, 440.0),
+        Filter::low_pass(rate, 14_000.0),
+    ]
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_reset: None,
+            odd_cycle: false,
+            blip: BlipBuf::new(CLOCK_RATE, DEFAULT_SAMPLE_RATE),
+            clock: 0,
+            last_output: 0.0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            filters: output_filters(DEFAULT_SAMPLE_RATE),
+            raw: Vec::new(),
+        }
+    }
+
+    // Output sample rate, resets the filters
+    pub fn set_sample_rate(&mut self, rate: f64) {
+        self.sample_rate = rate;
+        self.blip.set_rates(CLOCK_RATE, rate);
+        self.filters = output_filters(rate);
+    }
+
+    // Stretch or squeeze the output slightly, for frontends keeping the
+    // audio queue from running dry or piling up. 1.0 is nominal
+    pub fn adjust_rate(&mut self, ratio: f64) {
+        self.blip.set_rates(CLOCK_RATE, self.sample_rate * ratio);
+    }
+
+    // Resample everything produced since the last call and append it to out
+    pub fn samples(&mut self, out: &mut Vec<i16>) {
+        self.end_blip_frame();
+
+        self.raw.clear();
+        self.blip.read_samples(&mut self.raw);
+        for &sample in &self.raw {
+            let sample = self
+                .filters
+                .iter_mut()
+                .fold(sample, |val, filter| filter.process(val));
+            out.push((sample * f32::from(i16::MAX)) as i16);
+        }
+    }
+
+    // Register write in $4000-$4017
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr & 0x03, val),
+            0x4004..=0x4007 => self.pulse2.write(addr & 0x03, val),
+            0x4008..=0x400b => self.triangle.write(addr & 0x03, val),
+            0x400c..=0x400f => self.noise.write(addr & 0x03, val),
+            0x4010..=0x4013 => self.dmc.write(addr & 0x03, val),
+            0x4015 => {
+                self.pulse1.length.set_enabled(val & 0x01 != 0);
+                self.pulse2.length.set_enabled(val & 0x02 != 0);
+                self.triangle.length.set_enabled(val & 0x04 != 0);
+                self.noise.length.set_enabled(val & 0x08 != 0);
+                self.dmc.set_enabled(val & 0x10 != 0);
+            }
+            0x4017 => {
+                self.five_step = val & 0x80 != 0;
+                self.irq_inhibit = val & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                // The sequence restarts 3 or 4 cycles later, depending on
+                // where the write falls in the APU cycle
+                self.frame_reset = Some(if self.odd_cycle { 4 } else { 3 });
+            }
+            _ => {}
+        }
+    }
+
+    // $4015 read, channel status and IRQ flags. Clears the frame IRQ
+    pub fn read_status(&mut self) -> u8 {
+        let mut val = 0;
+        for (bit, active) in [
+            self.pulse1.length.active(),
+            self.pulse2.length.active(),
+            self.triangle.length.active(),
+            self.noise.length.active(),
+            self.dmc.active(),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            val |= u8::from(active) << bit;
+        }
+        val |= u8::from(self.frame_irq) << 6;
+        val |= u8::from(self.dmc.irq) << 7;
+        self.frame_irq = false;
+        val
+    }
+
+    // Run for one CPU cycle. Expansion audio from the cartridge is mixed in
+    pub fn tick(&mut self, expansion: f32) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        self.clock_frame_counter();
+
+        let output = self.output() + expansion;
+        if output != self.last_output {
+            self.blip.add_delta(self.clock, output - self.last_output);
+            self.last_output = output;
+        }
+        self.clock += 1;
+        if self.clock == BLIP_FRAME {
+            self.end_blip_frame();
+        }
+    }
+
+    fn end_blip_frame(&mut self) {
+        self.blip.end_frame(self.clock);
+        self.clock = 0;
+    }
+
+    fn clock_frame_counter(&mut self) {
+        if let Some(delay) = self.frame_reset {
+            if delay == 0 {
+                self.frame_reset = None;
+                self.frame_cycle = 0;
+                // Switching to 5-step clocks everything right away
+                if self.five_step {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+                return;
+            }
+            self.frame_reset = Some(delay - 1);
+        }
+
+        self.frame_cycle += 1;
+        match (self.frame_cycle, self.five_step) {
+            (STEP_1 | STEP_3, _) => self.quarter_frame(),
+            (STEP_2, _) | (STEP_4, false) | (STEP_5, true) => {
+                self.quarter_frame();
+                self.half_frame();
+            }
+            _ => {}
+        }
+
+        if !self.five_step && (STEP_4 - 1..=STEP_4_END).contains(&self.frame_cycle) {
+            self.frame_irq |= !self.irq_inhibit;
+        }
+
+        let end = if self.five_step {
+            STEP_5_END
+        } else {
+            STEP_4_END
+        };
+        if self.frame_cycle == end {
+            self.frame_cycle = 0;
+        }
+    }
+
+    // Envelopes and the triangle's linear counter
+    fn quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.triangle.clock_linear();
+        self.noise.envelope.clock();
+    }
+
+    // Length counters and sweeps
+    fn half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.length.clock();
+        self.pulse2.clock_sweep();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+    }
+
+    // Address the DMC wants fetched, if its buffer is empty
+    pub fn dmc_request(&mut self) -> Option<u16> {
+        self.dmc.request()
+    }
+
+    // Hand the DMC the byte it asked for
+    pub fn dmc_fill(&mut self, val: u8) {
+        self.dmc.fill(val);
+    }
+
+    // Frame counter or DMC IRQ asserted
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    // Mixer output, 0.0 to about 1.0. The DAC's two resistor networks
+    // don't sum linearly, this is the usual approximation
+    fn output(&self) -> f32 {
+        let pulse = f32::from(self.pulse1.output() + self.pulse2.output());
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = f32::from(self.triangle.output()) / 8227.0
+            + f32::from(self.noise.output()) / 12241.0
+            + f32::from(self.dmc.output()) / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(apu: &mut Apu, cycles: u32) {
+        for _ in 0..cycles {
+            apu.tick(0.0);
+        }
+    }
+
+    #[test]
+    fn length_counter() {
+        let mut apu = Apu::new();
+        // Loads are ignored while the channel is disabled
+        apu.write(0x4003, 0x18);
+        assert_eq!(apu.read_status() & 0x01, 0);
+
+        // Index 3 is a length of 2, counted down on half frames
+        apu.write(0x4015, 0x01);
+        apu.write(0x4003, 0x18);
+        assert_eq!(apu.read_status() & 0x01, 0x01);
+        run(&mut apu, STEP_2);
+        assert_eq!(apu.read_status() & 0x01, 0x01);
+        run(&mut apu, STEP_4 - STEP_2);
+        assert_eq!(apu.read_status() & 0x01, 0);
+
+        // Halt stops it, disabling clears it
+        apu.write(0x4000, 0x20);
+        apu.write(0x4003, 0x18);
+        run(&mut apu, STEP_4_END);
+        assert_eq!(apu.read_status() & 0x01, 0x01);
+        apu.write(0x4015, 0x00);
+        assert_eq!(apu.read_status() & 0x01, 0);
+    }
+
+    #[test]
+    fn frame_irq() {
+        let mut apu = Apu::new();
+        run(&mut apu, STEP_4 - 2);
+        assert!(!apu.irq());
+        run(&mut apu, 1);
+        assert!(apu.irq());
+        // Reading $4015 clears it, but it's set again until the sequence
+        // ends
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        run(&mut apu, 1);
+        assert!(apu.irq());
+        run(&mut apu, 1);
+        apu.read_status();
+        run(&mut apu, 1);
+        assert!(!apu.irq());
+
+        // Inhibiting clears and blocks it
+        run(&mut apu, STEP_4);
+        assert!(apu.irq());
+        apu.write(0x4017, 0x40);
+        assert!(!apu.irq());
+        run(&mut apu, 2 * STEP_4_END);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn five_step_sequence() {
+        let mut apu = Apu::new();
+        apu.write(0x4015, 0x01);
+        apu.write(0x4003, 0x18);
+
+        // Switching clocks a half frame right away, 3 or 4 cycles later
+        apu.write(0x4017, 0x80);
+        run(&mut apu, 4);
+        assert_eq!(apu.read_status() & 0x01, 0x01);
+        // The next half frames are at steps 2 and 5, with no IRQ
+        run(&mut apu, STEP_2);
+        assert_eq!(apu.read_status() & 0x01, 0);
+        run(&mut apu, 2 * STEP_5_END);
+        assert!(!apu.irq());
+    }
+}
@@ -0,0 +1,71 @@
+use super::envelope::Envelope;
+use super::length::LengthCounter;
+
+/// Timer periods in CPU cycles, NTSC
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+/// Noise channel, $400C-$400F
+pub struct Noise {
+    /// 15 bit linear feedback shift register
+    shift: u16,
+    /// Short mode, feedback from bit 6 instead of bit 1
+    short: bool,
+    period: u16,
+    timer: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Self {
+            shift: 1,
+            short: false,
+            period: PERIOD_TABLE[0],
+            timer: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    // Register write, addr is 0-3
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0 => {
+                self.length.halt = val & 0x20 != 0;
+                self.envelope.write(val);
+            }
+            1 => {}
+            2 => {
+                self.short = val & 0x80 != 0;
+                self.period = PERIOD_TABLE[(val & 0x0f) as usize];
+            }
+            _ => {
+                self.length.load(val);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    // CPU cycle clock
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift & 1 != 0 || !self.length.active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use super::envelope::Envelope;
+use super::length::LengthCounter;
+
+/// Duty cycle waveforms, 12.5%, 25%, 50% and 25% negated
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// Pulse channel, $4000-$4003 and $4004-$4007
+pub struct Pulse {
+    /// Pulse 1 negates its sweep in ones' complement
+    ones_complement: bool,
+    /// Has a sweep unit, MMC5's pulses don't and never mute on period
+    sweep_unit: bool,
+    duty: u8,
+    /// Position in the duty cycle
+    step: u8,
+    /// 11 bit timer period and counter, clocked every other CPU cycle
+    period: u16,
+    timer: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    sweep: Sweep,
+}
+
+/// Sweep unit, bends the period up or down every few half frames
+#[derive(Default)]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    divider: u8,
+    reload: bool,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Self {
+            ones_complement,
+            sweep_unit: true,
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            sweep: Sweep::default(),
+        }
+    }
+
+    // Pulse without a sweep unit, as on MMC5
+    pub fn without_sweep() -> Self {
+        Self {
+            sweep_unit: false,
+            ..Self::new(false)
+        }
+    }
+
+    // Register write, addr is 0-3
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0 => {
+                self.duty = val >> 6;
+                self.length.halt = val & 0x20 != 0;
+                self.envelope.write(val);
+            }
+            1 if self.sweep_unit => {
+                self.sweep.enabled = val & 0x80 != 0;
+                self.sweep.period = (val >> 4) & 0x07;
+                self.sweep.negate = val & 0x08 != 0;
+                self.sweep.shift = val & 0x07;
+                self.sweep.reload = true;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x0700) | u16::from(val),
+            _ => {
+                self.period = (self.period & 0x00ff) | (u16::from(val & 0x07) << 8);
+                self.length.load(val);
+                self.envelope.restart();
+                self.step = 0;
+            }
+        }
+    }
+
+    // APU cycle clock, every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Half frame clock
+    pub fn clock_sweep(&mut self) {
+        if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.muted() {
+            self.period = self.target_period();
+        }
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+    }
+
+    // Period the sweep is heading for, worked out all the time
+    fn target_period(&self) -> u16 {
+        let change = self.period >> self.sweep.shift;
+        if !self.sweep.negate {
+            self.period + change
+        } else if self.ones_complement {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    // Too low a period or a sweep target out of range silences the channel,
+    // even with the sweep disabled
+    fn muted(&self) -> bool {
+        self.sweep_unit && (self.period < 8 || self.target_period() > 0x7ff)
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.muted()
+            || !self.length.active()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
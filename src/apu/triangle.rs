@@ -0,0 +1,73 @@
+use super::length::LengthCounter;
+
+/// Triangle waveform, one step per timer clock
+#[rustfmt::skip]
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// Triangle channel, $4008-$400B
+#[derive(Default)]
+pub struct Triangle {
+    step: u8,
+    /// 11 bit timer period and counter, clocked every CPU cycle
+    period: u16,
+    timer: u16,
+    pub length: LengthCounter,
+    /// Linear counter reload value
+    linear_period: u8,
+    linear: u8,
+    linear_reload: bool,
+    /// Also halts the length counter
+    control: bool,
+}
+
+impl Triangle {
+    // Register write, addr is 0-3
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0 => {
+                self.control = val & 0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_period = val & 0x7f;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x0700) | u16::from(val),
+            _ => {
+                self.period = (self.period & 0x00ff) | (u16::from(val & 0x07) << 8);
+                self.length.load(val);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    // CPU cycle clock. The sequencer only moves while both counters are
+    // running, so the output holds its level when silenced
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.linear > 0 && self.length.active() {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Quarter frame clock
+    pub fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear = self.linear_period;
+        } else if self.linear > 0 {
+            self.linear -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+}
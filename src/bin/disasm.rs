@@ -0,0 +1,28 @@
+use std::{env, fs};
+
+use nes::cartridge::Cartridge;
+use nes::cpu::mos6502::disasm;
+
+const BANK_SIZE: usize = 0x4000;
+
+// Usage: disasm <rom.nes>
+// Dumps PRG ROM. Carts up to 32 KB are shown where NROM maps them, larger
+// ones bank by bank, switchable banks at $8000 and the last one at $C000
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let rom_file = env::args().nth(1).expect("Missing ROM file");
+    let cart = Cartridge::new(&fs::read(rom_file)?)?;
+    let prg = &cart.prg_rom;
+
+    if prg.len() <= 2 * BANK_SIZE {
+        print!("{}", disasm::listing(prg, (0x10000 - prg.len()) as u16));
+        return Ok(());
+    }
+
+    let banks = prg.chunks(BANK_SIZE).count();
+    for (i, bank) in prg.chunks(BANK_SIZE).enumerate() {
+        let base = if i == banks - 1 { 0xc000 } else { 0x8000 };
+        println!("; bank {i}");
+        print!("{}", disasm::listing(bank, base));
+    }
+    Ok(())
+}
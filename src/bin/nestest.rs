@@ -0,0 +1,21 @@
+use std::{env, fs, io::BufReader, process};
+
+use nes::cpu::nestest;
+
+// Usage: nestest <nestest.nes> <nestest.log>
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = env::args().skip(1);
+    let rom = fs::read(args.next().expect("Missing ROM file"))?;
+    let log = BufReader::new(fs::File::open(args.next().expect("Missing log file"))?);
+
+    match nestest::run(&rom, log)? {
+        None => println!("Trace matches the reference log"),
+        Some(div) => {
+            println!("Diverged at line {}", div.line);
+            println!("expected: {}", div.expected);
+            println!("actual:   {}", div.actual);
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
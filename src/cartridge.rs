@@ -0,0 +1,424 @@
+use std::{error, fmt};
+
+use crate::ppu::Mirroring;
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_ROM_UNIT: usize = 0x4000;
+const CHR_ROM_UNIT: usize = 0x2000;
+const PRG_RAM_UNIT: usize = 0x2000;
+const MAGIC: [u8; 4] = *b"NES\x1a";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomError {
+    /// The file is smaller than the 16 byte header
+    MissingHeader(usize),
+    /// The first four bytes aren't "NES\x1A"
+    BadMagic([u8; 4]),
+    /// The file ends before a section described by the header
+    Truncated {
+        section: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    /// The header gives no PRG ROM, there's nothing for the CPU to run
+    EmptyPrgRom,
+    /// The header asks for a mapper we don't implement
+    UnsupportedMapper(u16),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingHeader(len) => {
+                write!(f, "file is {len} bytes, too small for an iNES header")
+            }
+            Self::BadMagic(magic) => write!(f, "bad iNES magic: {magic:02x?}"),
+            Self::Truncated {
+                section,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{section} is truncated: expected {expected} bytes, found {actual}"
+            ),
+            Self::EmptyPrgRom => write!(f, "header gives no PRG ROM"),
+            Self::UnsupportedMapper(n) => write!(f, "mapper {n} is not supported"),
+        }
+    }
+}
+
+impl error::Error for RomError {}
+
+/// CPU/PPU timing the cartridge was made for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    /// Runs on both NTSC and PAL consoles
+    Multi,
+    Dendy,
+}
+
+/// Console the cartridge was made for. iNES only tells Vs. System and
+/// Playchoice 10 apart, NES 2.0 adds the Vs. details and extended types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    /// Regular NES/Famicom/Dendy
+    Nes,
+    /// Vs. System with its PPU and hardware type
+    VsSystem { ppu: u8, hardware: u8 },
+    /// Playchoice 10
+    Playchoice,
+    /// Extended console type from byte 13
+    Extended(u8),
+}
+
+/// Parsed iNES or NES 2.0 header
+#[derive(Debug, Clone)]
+pub struct RomHeader {
+    /// Header is in the NES 2.0 format
+    pub nes2: bool,
+    /// iNES mapper number, 12 bits on NES 2.0
+    pub mapper: u16,
+    /// Submapper number, always 0 on iNES
+    pub submapper: u8,
+    /// PRG ROM size in bytes
+    pub prg_rom_size: usize,
+    /// CHR ROM size in bytes, 0 if the board uses CHR RAM
+    pub chr_rom_size: usize,
+    /// Volatile PRG RAM size in bytes
+    pub prg_ram_size: usize,
+    /// Battery backed PRG RAM size in bytes
+    pub prg_nvram_size: usize,
+    /// Volatile CHR RAM size in bytes, only known on NES 2.0
+    pub chr_ram_size: usize,
+    /// Battery backed CHR RAM size in bytes, only known on NES 2.0
+    pub chr_nvram_size: usize,
+    /// Hardwired nametable mirroring
+    pub mirroring: Mirroring,
+    /// PRG RAM is battery backed
+    pub battery: bool,
+    /// A 512 byte trainer sits between the header and PRG ROM
+    pub trainer: bool,
+    /// CPU/PPU timing
+    pub region: Region,
+    /// Console type
+    pub console: ConsoleType,
+    /// Default expansion device, NES 2.0 only
+    /// (0: unspecified; 1: standard controllers; ...)
+    pub expansion_device: u8,
+}
+
+impl RomHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, RomError> {
+        if rom.len() < HEADER_SIZE {
+            return Err(RomError::MissingHeader(rom.len()));
+        }
+
+        let magic = [rom[0], rom[1], rom[2], rom[3]];
+        if magic != MAGIC {
+            return Err(RomError::BadMagic(magic));
+        }
+
+        let (flags6, flags7) = (rom[6], rom[7]);
+        let mirroring = if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery = flags6 & 0x02 != 0;
+        let mapper = u16::from((flags7 & 0xf0) | (flags6 >> 4));
+
+        if flags7 & 0x0c == 0x08 {
+            return Ok(Self {
+                nes2: true,
+                mapper: mapper | (u16::from(rom[8] & 0x0f) << 8),
+                submapper: rom[8] >> 4,
+                prg_rom_size: rom_size(rom[4], rom[9] & 0x0f, PRG_ROM_UNIT),
+                chr_rom_size: rom_size(rom[5], rom[9] >> 4, CHR_ROM_UNIT),
+                prg_ram_size: ram_size(rom[10] & 0x0f),
+                prg_nvram_size: ram_size(rom[10] >> 4),
+                chr_ram_size: ram_size(rom[11] & 0x0f),
+                chr_nvram_size: ram_size(rom[11] >> 4),
+                mirroring,
+                battery,
+                trainer: flags6 & 0x04 != 0,
+                region: match rom[12] & 0x03 {
+                    0 => Region::Ntsc,
+                    1 => Region::Pal,
+                    2 => Region::Multi,
+                    _ => Region::Dendy,
+                },
+                console: match flags7 & 0x03 {
+                    0 => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem {
+                        ppu: rom[13] & 0x0f,
+                        hardware: rom[13] >> 4,
+                    },
+                    2 => ConsoleType::Playchoice,
+                    _ => ConsoleType::Extended(rom[13] & 0x0f),
+                },
+                expansion_device: rom[15] & 0x3f,
+            });
+        }
+
+        // Old dumping tools wrote junk like "DiskDude!" into bytes 7-15,
+        // in which case none of them can be trusted and they're read as 0
+        let (flags7, flags8, flags9) = if rom[12..HEADER_SIZE].iter().any(|&b| b != 0) {
+            (0, 0, 0)
+        } else {
+            (flags7, rom[8], rom[9])
+        };
+        let mapper = u16::from((flags7 & 0xf0) | (flags6 >> 4));
+
+        // A value of 0 infers 8 KB for compatibility
+        let prg_ram = flags8.max(1) as usize * PRG_RAM_UNIT;
+
+        Ok(Self {
+            nes2: false,
+            mapper,
+            submapper: 0,
+            prg_rom_size: rom[4] as usize * PRG_ROM_UNIT,
+            chr_rom_size: rom[5] as usize * CHR_ROM_UNIT,
+            prg_ram_size: if battery { 0 } else { prg_ram },
+            prg_nvram_size: if battery { prg_ram } else { 0 },
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            mirroring,
+            battery,
+            trainer: flags6 & 0x04 != 0,
+            region: if flags9 & 0x01 != 0 {
+                Region::Pal
+            } else {
+                Region::Ntsc
+            },
+            console: match flags7 & 0x03 {
+                1 => ConsoleType::VsSystem {
+                    ppu: 0,
+                    hardware: 0,
+                },
+                2 => ConsoleType::Playchoice,
+                _ => ConsoleType::Nes,
+            },
+            expansion_device: 0,
+        })
+    }
+}
+
+// NES 2.0 ROM size from the LSB byte and MSB nibble. An MSB nibble of $F
+// switches the LSB to exponent-multiplier notation: 2^E * (MM * 2 + 1)
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0f {
+        let (exp, mul) = (u32::from(lsb >> 2), (lsb & 0x03) as usize * 2 + 1);
+        1usize
+            .checked_shl(exp)
+            .and_then(|size| size.checked_mul(mul))
+            .unwrap_or(usize::MAX)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+// NES 2.0 RAM size from a shift count, 64 << n bytes or none if 0
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+pub struct Cartridge {
+    /// Parsed header
+    pub header: RomHeader,
+    /// 512 byte trainer, loaded at $7000 by some copiers
+    pub trainer: Option<Vec<u8>>,
+    /// PRG ROM
+    pub prg_rom: Vec<u8>,
+    /// CHR ROM, empty if the board uses CHR RAM
+    pub chr_rom: Vec<u8>,
+}
+
+impl Cartridge {
+    pub fn new(rom: &[u8]) -> Result<Self, RomError> {
+        let header = RomHeader::parse(rom)?;
+        if header.prg_rom_size == 0 {
+            return Err(RomError::EmptyPrgRom);
+        }
+        let mut offset = HEADER_SIZE;
+
+        let mut section = |name: &'static str, len: usize| {
+            let end = offset.saturating_add(len);
+            let data = rom.get(offset..end).ok_or(RomError::Truncated {
+                section: name,
+                expected: len,
+                actual: rom.len().saturating_sub(offset),
+            })?;
+            offset += len;
+            Ok(data.to_vec())
+        };
+
+        let trainer = if header.trainer {
+            Some(section("trainer", TRAINER_SIZE)?)
+        } else {
+            None
+        };
+        let prg_rom = section("PRG ROM", header.prg_rom_size)?;
+        let chr_rom = section("CHR ROM", header.chr_rom_size)?;
+
+        Ok(Self {
+            header,
+            trainer,
+            prg_rom,
+            chr_rom,
+        })
+    }
+}
+
+// NROM image for tests: 16 KB of PRG ROM, seen at both $8000 and $C000,
+// with the program at $C000 and every vector pointing at it, and 8 KB of
+// blank CHR ROM
+#[cfg(test)]
+pub(crate) fn test_image(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; HEADER_SIZE + PRG_ROM_UNIT + CHR_ROM_UNIT];
+    rom[..8].copy_from_slice(b"NES\x1a\x01\x01\x00\x00");
+    rom[HEADER_SIZE..HEADER_SIZE + program.len()].copy_from_slice(program);
+    for vector in [0xfffa, 0xfffc, 0xfffe] {
+        set_test_vector(&mut rom, vector, 0xc000);
+    }
+    rom
+}
+
+// Point a vector in a test_image at an address
+#[cfg(test)]
+pub(crate) fn set_test_vector(rom: &mut [u8], vector: u16, addr: u16) {
+    let offset = HEADER_SIZE + (vector as usize & (PRG_ROM_UNIT - 1));
+    rom[offset..offset + 2].copy_from_slice(&addr.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16 byte header with the magic and the given bytes 4-15
+    fn header(bytes: [u8; 12]) -> [u8; HEADER_SIZE] {
+        let mut rom = [0; HEADER_SIZE];
+        rom[..4].copy_from_slice(&MAGIC);
+        rom[4..].copy_from_slice(&bytes);
+        rom
+    }
+
+    #[test]
+    fn ines() {
+        let h = RomHeader::parse(&header([2, 1, 0x11, 0x40, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert!(!h.nes2);
+        assert_eq!(h.mapper, 0x41);
+        assert_eq!(h.submapper, 0);
+        assert_eq!(h.prg_rom_size, 0x8000);
+        assert_eq!(h.chr_rom_size, 0x2000);
+        assert_eq!(h.prg_ram_size, 0x2000);
+        assert_eq!(h.prg_nvram_size, 0);
+        assert_eq!(h.mirroring, Mirroring::Vertical);
+        assert_eq!(h.region, Region::Ntsc);
+        assert_eq!(h.console, ConsoleType::Nes);
+    }
+
+    #[test]
+    fn ines_battery_pal_four_screen() {
+        let h = RomHeader::parse(&header([1, 0, 0x0a, 0x02, 4, 0x01, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert!(h.battery);
+        assert_eq!(h.prg_ram_size, 0);
+        assert_eq!(h.prg_nvram_size, 0x8000);
+        assert_eq!(h.mirroring, Mirroring::FourScreen);
+        assert_eq!(h.region, Region::Pal);
+        assert_eq!(h.console, ConsoleType::Playchoice);
+    }
+
+    #[test]
+    fn diskdude() {
+        let mut rom = header([1, 1, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom[7..].copy_from_slice(b"DiskDude!");
+        let h = RomHeader::parse(&rom).unwrap();
+        assert!(!h.nes2);
+        assert_eq!(h.mapper, 4);
+        assert_eq!(h.region, Region::Ntsc);
+        assert_eq!(h.prg_ram_size, PRG_RAM_UNIT);
+        assert_eq!(h.console, ConsoleType::Nes);
+    }
+
+    #[test]
+    fn nes2() {
+        let rom = header([
+            2, 1, 0x01, 0x59, 0x21, 0x01, 0x70, 0x07, 0x03, 0x21, 0, 0x01,
+        ]);
+        let h = RomHeader::parse(&rom).unwrap();
+        assert!(h.nes2);
+        assert_eq!(h.mapper, 0x150);
+        assert_eq!(h.submapper, 2);
+        assert_eq!(h.prg_rom_size, 0x102 * PRG_ROM_UNIT);
+        assert_eq!(h.chr_rom_size, 0x2000);
+        assert_eq!(h.prg_ram_size, 0);
+        assert_eq!(h.prg_nvram_size, 0x2000);
+        assert_eq!(h.chr_ram_size, 0x2000);
+        assert_eq!(h.chr_nvram_size, 0);
+        assert_eq!(h.region, Region::Dendy);
+        assert_eq!(
+            h.console,
+            ConsoleType::VsSystem {
+                ppu: 1,
+                hardware: 2
+            }
+        );
+        assert_eq!(h.expansion_device, 1);
+    }
+
+    #[test]
+    fn nes2_exponent_sizes() {
+        // PRG 2^10 * 3, CHR 2^13 * 1
+        let rom = header([0x29, 0x34, 0, 0x0b, 0, 0xff, 0, 0, 0, 0x05, 0, 0]);
+        let h = RomHeader::parse(&rom).unwrap();
+        assert_eq!(h.prg_rom_size, 3072);
+        assert_eq!(h.chr_rom_size, 0x2000);
+        assert_eq!(h.console, ConsoleType::Extended(5));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(rom_size(2, 0, PRG_ROM_UNIT), 0x8000);
+        assert_eq!(rom_size(0x00, 0x0f, PRG_ROM_UNIT), 1);
+        assert_eq!(rom_size(0x07, 0x0f, PRG_ROM_UNIT), 7 * 2);
+        assert_eq!(rom_size(0xff, 0x0f, PRG_ROM_UNIT), usize::MAX);
+        assert_eq!(ram_size(0), 0);
+        assert_eq!(ram_size(1), 128);
+        assert_eq!(ram_size(7), 0x2000);
+    }
+
+    #[test]
+    fn bad_files() {
+        assert_eq!(
+            RomHeader::parse(b"NES").unwrap_err(),
+            RomError::MissingHeader(3)
+        );
+        let mut rom = header([1; 12]);
+        rom[3] = 0;
+        assert_eq!(
+            RomHeader::parse(&rom).unwrap_err(),
+            RomError::BadMagic(*b"NES\0")
+        );
+        assert_eq!(
+            Cartridge::new(&header([0; 12])).err(),
+            Some(RomError::EmptyPrgRom)
+        );
+        assert_eq!(
+            Cartridge::new(&header([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])).err(),
+            Some(RomError::Truncated {
+                section: "PRG ROM",
+                expected: PRG_ROM_UNIT,
+                actual: 0,
+            })
+        );
+    }
+}
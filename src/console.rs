@@ -0,0 +1,55 @@
+use std::cell::Ref;
+
+use crate::cartridge::{Cartridge, RomError};
+use crate::controller::Buttons;
+use crate::cpu::Cpu;
+use crate::mapper;
+use crate::mem::Memory;
+use crate::ppu::palette::Pixel;
+
+pub struct Console {
+    pub cpu: Cpu,
+    pub mem: Memory,
+}
+
+impl Console {
+    // Power on with the cartridge inserted
+    pub fn new(cart: Cartridge) -> Result<Self, RomError> {
+        let mut mem = Memory::new(mapper::new(cart)?);
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut mem);
+        Ok(Self { cpu, mem })
+    }
+
+    // Run until the PPU finishes a frame
+    pub fn run_frame(&mut self) {
+        while !self.mem.poll_frame() {
+            self.cpu.step(&mut self.mem);
+        }
+    }
+
+    // Buttons held on a controller port, 0 or 1
+    pub fn set_buttons(&mut self, port: usize, buttons: Buttons) {
+        self.mem.set_buttons(port, buttons);
+    }
+
+    // Sample rate of audio_samples
+    pub fn set_sample_rate(&mut self, rate: f64) {
+        self.mem.set_sample_rate(rate);
+    }
+
+    // Resample slightly faster or slower than nominal, 1.0 is nominal
+    pub fn adjust_audio_rate(&mut self, ratio: f64) {
+        self.mem.adjust_audio_rate(ratio);
+    }
+
+    // Append the audio produced since the last call, mono 16 bit
+    pub fn audio_samples(&mut self, out: &mut Vec<i16>) {
+        self.mem.audio_samples(out);
+    }
+
+    // Pixels of the last completed frame, 256x240
+    pub fn frame(&self) -> Ref<'_, [Pixel]> {
+        self.mem.frame()
+    }
+}
@@ -0,0 +1,103 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// Standard controller buttons, in the order they're shifted out
+    #[derive(Default)]
+    pub struct Buttons: u8 {
+        const A = 0b00000001;
+        const B = 0b00000010;
+        const SELECT = 0b00000100;
+        const START = 0b00001000;
+        const UP = 0b00010000;
+        const DOWN = 0b00100000;
+        const LEFT = 0b01000000;
+        const RIGHT = 0b10000000;
+    }
+}
+
+/// Standard controller, a 4021 shift register behind $4016/$4017
+#[derive(Default)]
+pub struct Controller {
+    /// Buttons currently held
+    buttons: Buttons,
+    /// Buttons latched at the last strobe, shifted out one per read
+    shift: u8,
+    /// While high the register keeps reloading and reads return A
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons.bits();
+        }
+    }
+
+    // $4016 write, bit 0 is the strobe
+    pub fn write(&mut self, val: u8) {
+        self.strobe = val & 0x01 != 0;
+        if self.strobe {
+            self.shift = self.buttons.bits();
+        }
+    }
+
+    // Serial read, bit 0. Ones are shifted in behind the buttons, so reads
+    // after the eighth return 1
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons.bits() & 0x01;
+        }
+        let bit = self.shift & 0x01;
+        self.shift = (self.shift >> 1) | 0x80;
+        bit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(controller: &mut Controller, count: usize) -> Vec<u8> {
+        (0..count).map(|_| controller.read()).collect()
+    }
+
+    #[test]
+    fn shifts_out_buttons_then_ones() {
+        let mut controller = Controller::default();
+        controller.set_buttons(Buttons::A | Buttons::START | Buttons::RIGHT);
+        controller.write(1);
+        controller.write(0);
+        assert_eq!(
+            read_all(&mut controller, 10),
+            [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]
+        );
+    }
+
+    #[test]
+    fn strobe_high_reads_a() {
+        let mut controller = Controller::default();
+        controller.write(1);
+        controller.set_buttons(Buttons::A);
+        assert_eq!(read_all(&mut controller, 3), [1, 1, 1]);
+        controller.set_buttons(Buttons::B);
+        assert_eq!(controller.read(), 0);
+        // Dropping the strobe keeps the last state
+        controller.write(0);
+        controller.set_buttons(Buttons::empty());
+        assert_eq!(read_all(&mut controller, 2), [0, 1]);
+    }
+
+    #[test]
+    fn latched_until_next_strobe() {
+        let mut controller = Controller::default();
+        controller.set_buttons(Buttons::UP);
+        controller.write(1);
+        controller.write(0);
+        controller.set_buttons(Buttons::DOWN);
+        assert_eq!(read_all(&mut controller, 6), [0, 0, 0, 0, 1, 0]);
+        controller.write(1);
+        controller.write(0);
+        assert_eq!(read_all(&mut controller, 6), [0, 0, 0, 0, 0, 1]);
+    }
+}
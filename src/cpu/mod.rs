@@ -1,11 +1,27 @@
-mod mos6502;
+pub mod mos6502;
+pub mod nestest;
+
+use std::io::Write;
 
 use crate::mem::Memory;
 use mos6502::Registers;
 
 pub struct Cpu {
     regs: Registers,
-    ticks: u32,
+    /// CPU cycles since power-on
+    ticks: u64,
+    /// NMI polled during the last instruction, serviced next
+    nmi: bool,
+    /// IRQ polled during the last instruction, serviced next
+    irq: bool,
+    /// Receives a nestest.log style line per instruction
+    tracer: Option<Box<dyn Write>>,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
@@ -13,10 +29,168 @@ impl Cpu {
         Self {
             regs: Registers::new(),
             ticks: 0,
+            nmi: false,
+            irq: false,
+            tracer: None,
+        }
+    }
+
+    // Power-on or reset button, jumps through the reset vector
+    pub fn reset(&mut self, mem: &mut Memory) {
+        let start = mem.cycles();
+        mos6502::reset(&mut self.regs, mem);
+        self.ticks += mem.cycles() - start;
+    }
+
+    // Run one instruction, or service a pending interrupt instead.
+    // Returns the cycles taken
+    pub fn step(&mut self, mem: &mut Memory) -> u32 {
+        let start = mem.cycles();
+
+        if std::mem::take(&mut self.nmi) {
+            mos6502::nmi(&mut self.regs, mem);
+        } else if std::mem::take(&mut self.irq) {
+            mos6502::irq(&mut self.regs, mem);
+        } else {
+            if let Some(tracer) = &mut self.tracer {
+                let line = mos6502::trace(&self.regs, mem, self.ticks);
+                if writeln!(tracer, "{line}").is_err() {
+                    self.tracer = None;
+                }
+            }
+
+            let op = mem.read8(self.regs.bump());
+            let irq_disabled = self.regs.irq_disabled();
+            mos6502::exec(op, &mut self.regs, mem);
+
+            // CLI, SEI and PLP change I on their last cycle, after the poll
+            let irq_disabled = match op {
+                0x58 | 0x78 | 0x28 => irq_disabled,
+                _ => self.regs.irq_disabled(),
+            };
+            self.nmi = mem.nmi_pending();
+            self.irq = mem.irq_pending() && !irq_disabled;
+        }
+
+        let cycles = mem.cycles() - start;
+        self.ticks += cycles;
+        cycles as u32
+    }
+
+    // Trace line for the next instruction
+    pub fn trace(&self, mem: &Memory) -> String {
+        mos6502::trace(&self.regs, mem, self.ticks)
+    }
+
+    // Log every instruction to the writer, None turns tracing off.
+    // Tracing stops on the first write error
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Write>>) {
+        self.tracer = tracer;
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.regs.set_pc(pc);
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{set_test_vector, test_image, Cartridge};
+    use crate::mapper;
+
+    // CPU reset into a program at $C000, with IRQs and NMIs going to $C010
+    fn setup(program: &[u8]) -> (Cpu, Memory) {
+        let mut rom = test_image(program);
+        set_test_vector(&mut rom, mos6502::NMI_VECTOR, 0xc010);
+        set_test_vector(&mut rom, mos6502::IRQ_VECTOR, 0xc010);
+        let mut mem = Memory::new(mapper::new(Cartridge::new(&rom).unwrap()).unwrap());
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut mem);
+        (cpu, mem)
+    }
+
+    // PC of the next instruction, from the trace line
+    fn pc(cpu: &Cpu, mem: &Memory) -> String {
+        cpu.trace(mem)[..4].to_string()
+    }
+
+    #[test]
+    fn reset() {
+        let (cpu, mem) = setup(&[0xea]);
+        assert_eq!(cpu.ticks(), 7);
+        let trace = cpu.trace(&mem);
+        assert!(trace.starts_with("C000"), "{trace}");
+        assert!(trace.contains("P:24 SP:FD"), "{trace}");
+    }
+
+    #[test]
+    fn brk() {
+        let (mut cpu, mut mem) = setup(&[0x00, 0xff]);
+        assert_eq!(cpu.step(&mut mem), 7);
+        assert_eq!(pc(&cpu, &mem), "C010");
+        // Return address skips the padding byte, B is set in the copy
+        assert_eq!(mem.peek8(0x01fd), 0xc0);
+        assert_eq!(mem.peek8(0x01fc), 0x02);
+        assert_eq!(mem.peek8(0x01fb), 0x34);
+    }
+
+    #[test]
+    fn nmi_at_vblank() {
+        // LDA #$80, STA $2000, JMP *
+        let (mut cpu, mut mem) = setup(&[0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0xc0]);
+        while pc(&cpu, &mem) != "C010" {
+            cpu.step(&mut mem);
         }
+        assert_eq!(mem.ppu_position().0, 241);
+        // B is clear in the pushed status
+        assert_eq!(mem.peek8(0x01fb) & 0x30, 0x20);
     }
 
-    pub fn step(&mut self, mem: &mut Memory) -> u8 {
-        0
+    #[test]
+    fn irq_poll_after_cli_and_sei() {
+        #[rustfmt::skip]
+        let mut program = vec![
+            0x4c, 0x00, 0xc0, // JMP *
+            0x58,             // CLI
+            0x78,             // SEI
+            0xea,             // NOP
+            0x58,             // CLI
+            0xea,             // NOP
+            0x4c, 0x08, 0xc0, // JMP *
+        ];
+        program.resize(0x10, 0);
+        program.push(0x40); // RTI
+
+        // Wait with I set for the APU frame IRQ
+        let (mut cpu, mut mem) = setup(&program);
+        while !mem.irq() {
+            cpu.step(&mut mem);
+        }
+        cpu.set_pc(0xc003);
+
+        // CLI then SEI lets the IRQ in once, after the SEI, with I pushed
+        cpu.step(&mut mem);
+        assert_eq!(pc(&cpu, &mem), "C004");
+        cpu.step(&mut mem);
+        assert_eq!(pc(&cpu, &mem), "C005");
+        assert_eq!(cpu.step(&mut mem), 7);
+        assert_eq!(pc(&cpu, &mem), "C010");
+        assert_eq!(mem.peek8(0x01fb) & 0x34, 0x24);
+        cpu.step(&mut mem);
+        assert_eq!(pc(&cpu, &mem), "C005");
+
+        // After a CLI the next instruction still runs first
+        cpu.step(&mut mem);
+        cpu.step(&mut mem);
+        assert_eq!(pc(&cpu, &mem), "C007");
+        cpu.step(&mut mem);
+        assert_eq!(pc(&cpu, &mem), "C008");
+        assert_eq!(cpu.step(&mut mem), 7);
+        assert_eq!(pc(&cpu, &mem), "C010");
     }
 }
@@ -0,0 +1,151 @@
+use std::fmt;
+
+use super::isa::{AddressingMode::*, Instruction};
+use super::{IRQ_VECTOR, NMI_VECTOR, RESET_VECTOR};
+
+/// A single disassembled instruction
+#[derive(Debug, Clone)]
+pub struct Line {
+    /// Address of the opcode
+    pub addr: u16,
+    /// Raw instruction bytes
+    pub bytes: Vec<u8>,
+    /// Mnemonic, e.g. "LDA", or ".db" for a truncated instruction
+    pub mnemonic: String,
+    /// Operand in assembler syntax, e.g. "($10),Y", empty when implied
+    pub operand: String,
+    /// Opcode is outside the documented instruction set
+    pub unofficial: bool,
+}
+
+impl fmt::Display for Line {
+    // 8000  A2 05    LDX #$05
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw: Vec<String> = self.bytes.iter().map(|b| format!("{b:02X}")).collect();
+        let star = if self.unofficial { '*' } else { ' ' };
+        let text = format!("{} {}", self.mnemonic, self.operand);
+        write!(
+            f,
+            "{:04X}  {:<8} {star}{}",
+            self.addr,
+            raw.join(" "),
+            text.trim_end()
+        )
+    }
+}
+
+// Disassemble the instruction at addr, reading bytes through `read`
+pub fn disassemble(addr: u16, read: impl Fn(u16) -> u8) -> Line {
+    let op = read(addr);
+    let Instruction(mode, opcode) = Instruction::from(op);
+    let bytes: Vec<u8> = (0..mode.len())
+        .map(|i| read(addr.wrapping_add(i)))
+        .collect();
+
+    let lo = bytes.get(1).copied().unwrap_or(0);
+    let abs = u16::from_le_bytes([lo, bytes.get(2).copied().unwrap_or(0)]);
+    let operand = match mode {
+        Implied => String::new(),
+        Accumulator => "A".to_string(),
+        Immediate => format!("#${lo:02X}"),
+        ZeroPage => format!("${lo:02X}"),
+        ZeroPageX => format!("${lo:02X},X"),
+        ZeroPageY => format!("${lo:02X},Y"),
+        Absolute => format!("${abs:04X}"),
+        AbsoluteX => format!("${abs:04X},X"),
+        AbsoluteY => format!("${abs:04X},Y"),
+        Indirect => format!("(${abs:04X})"),
+        IndirectX => format!("(${lo:02X},X)"),
+        IndirectY => format!("(${lo:02X}),Y"),
+        Relative => format!(
+            "${:04X}",
+            addr.wrapping_add(2).wrapping_add(lo as i8 as u16)
+        ),
+    };
+
+    Line {
+        addr,
+        bytes,
+        mnemonic: format!("{opcode:?}"),
+        operand,
+        unofficial: opcode.unofficial(op),
+    }
+}
+
+// Disassemble a block of code loaded at base, e.g. a PRG ROM bank. An
+// instruction running past the end is emitted as raw .db bytes
+pub fn disassemble_range(data: &[u8], base: u16) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let addr = base.wrapping_add(offset as u16);
+        let len = Instruction::from(data[offset]).0.len() as usize;
+
+        let line = if offset + len <= data.len() {
+            disassemble(addr, |a| data[a.wrapping_sub(base) as usize])
+        } else {
+            let raw: Vec<String> = data[offset..].iter().map(|b| format!("${b:02X}")).collect();
+            Line {
+                addr,
+                bytes: data[offset..].to_vec(),
+                mnemonic: ".db".to_string(),
+                operand: raw.join(","),
+                unofficial: false,
+            }
+        };
+
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+
+    lines
+}
+
+// Full listing of a block loaded at base. If the block covers the CPU
+// vectors, the instructions they point at get NMI/RESET/IRQ labels
+pub fn listing(data: &[u8], base: u16) -> String {
+    let end = base as usize + data.len();
+    let mut labels = Vec::new();
+    if end == 0x10000 && data.len() >= 6 {
+        let vector = |addr: u16| {
+            let i = (addr - base) as usize;
+            u16::from_le_bytes([data[i], data[i + 1]])
+        };
+        labels.push((vector(NMI_VECTOR), "NMI"));
+        labels.push((vector(RESET_VECTOR), "RESET"));
+        labels.push((vector(IRQ_VECTOR), "IRQ"));
+    }
+
+    let mut out = String::new();
+    for line in disassemble_range(data, base) {
+        for (_, label) in labels.iter().filter(|(addr, _)| *addr == line.addr) {
+            out.push_str(&format!("{label}:\n"));
+        }
+        out.push_str(&format!("{line}\n"));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vector_labels() {
+        // NOP at $FFF8, then NMI and RESET at $FFF8 and IRQ at $FFF9
+        let data = [0xea, 0xea, 0xf8, 0xff, 0xf8, 0xff, 0xf9, 0xff];
+        let out = listing(&data, 0xfff8);
+        assert!(out.starts_with("NMI:\nRESET:\n"), "{out}");
+        assert!(out.contains("IRQ:\n"), "{out}");
+    }
+
+    #[test]
+    fn block_too_small_for_vectors() {
+        for len in 1..6 {
+            let data = vec![0xea; len];
+            let out = listing(&data, (0x10000 - len) as u16);
+            assert_eq!(out.lines().count(), len, "{out}");
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use self::{AddressingMode::*, Opcode::*};
-use super::{Psr, Registers};
+use super::{Psr, Registers, IRQ_VECTOR, NMI_VECTOR, RESET_VECTOR};
 use crate::mem::Memory;
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
-enum AddressingMode {
+pub(super) enum AddressingMode {
     Accumulator,
     Immediate,
     Absolute,
@@ -20,7 +20,8 @@ enum AddressingMode {
 }
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
-enum Opcode {
+#[allow(clippy::upper_case_acronyms)]
+pub(super) enum Opcode {
     ADC,
     SBC,
     AND,
@@ -91,288 +92,383 @@ enum Opcode {
     SRE,
     SKB,
     IGN,
+    LAS,
+    LXA,
+    XAA,
+    AHX,
+    SHX,
+    SHY,
+    TAS,
+    KIL,
 }
 
-/// Argument, address, extra cycle
-struct Operand(u8, u16, u32);
+// XAA and LXA mix the accumulator with a chip dependent "magic" constant
+// before the AND. Real consoles vary with temperature and batch, we use the
+// values most test ROMs and games accept: $EE for XAA, and $FF for LXA which
+// makes it behave like LAX #imm
+const XAA_MAGIC: u8 = 0xee;
+const LXA_MAGIC: u8 = 0xff;
 
-/// Addressing mode, opcode, cycles
-struct Instruction(AddressingMode, Opcode, u32);
+/// Effective address, page crossed
+struct Operand(u16, bool);
+
+/// Addressing mode, opcode
+pub(super) struct Instruction(pub AddressingMode, pub Opcode);
+
+/// How an instruction uses its effective address
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+enum Access {
+    Read,
+    Write,
+    /// Read-modify-write, writes the old value back before the new one
+    Modify,
+}
+
+impl AddressingMode {
+    // Instruction length in bytes, including the opcode
+    pub(super) fn len(self) -> u16 {
+        match self {
+            Implied | Accumulator => 1,
+            Absolute | AbsoluteX | AbsoluteY | Indirect => 3,
+            _ => 2,
+        }
+    }
+}
+
+impl Opcode {
+    fn access(self) -> Access {
+        match self {
+            STA | STX | STY | SAX | AHX | SHX | SHY | TAS => Access::Write,
+            ASL | LSR | ROL | ROR | INC | DEC | DCP | ISC | RLA | RRA | SLO | SRE => Access::Modify,
+            _ => Access::Read,
+        }
+    }
+
+    // Whether the opcode byte is outside the documented instruction set,
+    // $EA is the only official NOP and $EB duplicates SBC #imm
+    pub(super) fn unofficial(self, op: u8) -> bool {
+        match self {
+            NOP => op != 0xea,
+            SBC => op == 0xeb,
+            ALR | ANC | ARR | AXS | LAX | SAX | DCP | ISC | RLA | RRA | SLO | SRE | SKB | IGN
+            | LAS | LXA | XAA | AHX | SHX | SHY | TAS | KIL => true,
+            _ => false,
+        }
+    }
+}
 
 impl From<u8> for Instruction {
     fn from(op: u8) -> Self {
         match op {
-            0x69 => Instruction(Immediate, ADC, 2),
-            0x65 => Instruction(ZeroPage, ADC, 3),
-            0x75 => Instruction(ZeroPageX, ADC, 4),
-            0x6d => Instruction(Absolute, ADC, 4),
-            0x7d => Instruction(AbsoluteX, ADC, 4),
-            0x79 => Instruction(AbsoluteY, ADC, 4),
-            0x61 => Instruction(IndirectX, ADC, 6),
-            0x71 => Instruction(IndirectY, ADC, 5),
-
-            0x29 => Instruction(Immediate, AND, 2),
-            0x25 => Instruction(ZeroPage, AND, 3),
-            0x35 => Instruction(ZeroPageX, AND, 4),
-            0x2d => Instruction(Absolute, AND, 4),
-            0x3d => Instruction(AbsoluteX, AND, 4),
-            0x39 => Instruction(AbsoluteY, AND, 4),
-            0x21 => Instruction(IndirectX, AND, 6),
-            0x31 => Instruction(IndirectY, AND, 5),
-
-            0x0a => Instruction(Accumulator, ASL, 2),
-            0x06 => Instruction(ZeroPage, ASL, 5),
-            0x16 => Instruction(ZeroPageX, ASL, 6),
-            0x0e => Instruction(Absolute, ASL, 6),
-            0x1e => Instruction(AbsoluteX, ASL, 7),
-
-            0x24 => Instruction(ZeroPage, BIT, 3),
-            0x2c => Instruction(Absolute, BIT, 4),
-
-            0x00 => Instruction(Implied, BRK, 7),
-
-            0x90 => Instruction(Relative, BCC, 2),
-            0xb0 => Instruction(Relative, BCS, 2),
-            0xf0 => Instruction(Relative, BEQ, 2),
-            0x30 => Instruction(Relative, BMI, 2),
-            0xd0 => Instruction(Relative, BNE, 2),
-            0x10 => Instruction(Relative, BPL, 2),
-            0x50 => Instruction(Relative, BVC, 2),
-            0x70 => Instruction(Relative, BVS, 2),
-
-            0x18 => Instruction(Implied, CLC, 2),
-            0xd8 => Instruction(Implied, CLD, 2),
-            0x58 => Instruction(Implied, CLI, 2),
-            0xb8 => Instruction(Implied, CLV, 2),
-
-            0xc9 => Instruction(Immediate, CMP, 2),
-            0xc5 => Instruction(ZeroPage, CMP, 3),
-            0xd5 => Instruction(ZeroPageX, CMP, 4),
-            0xcd => Instruction(Absolute, CMP, 4),
-            0xdd => Instruction(AbsoluteX, CMP, 4),
-            0xd9 => Instruction(AbsoluteY, CMP, 4),
-            0xc1 => Instruction(IndirectX, CMP, 6),
-            0xd1 => Instruction(IndirectY, CMP, 5),
-
-            0xe0 => Instruction(Immediate, CPX, 2),
-            0xe4 => Instruction(ZeroPage, CPX, 3),
-            0xec => Instruction(Absolute, CPX, 4),
-
-            0xc0 => Instruction(Immediate, CPY, 2),
-            0xc4 => Instruction(ZeroPage, CPY, 3),
-            0xcc => Instruction(Absolute, CPY, 4),
-
-            0xc6 => Instruction(ZeroPage, DEC, 5),
-            0xd6 => Instruction(ZeroPageX, DEC, 6),
-            0xce => Instruction(Absolute, DEC, 6),
-            0xde => Instruction(AbsoluteX, DEC, 7),
-
-            0xca => Instruction(Implied, DEX, 2),
-            0x88 => Instruction(Implied, DEY, 2),
-
-            0x49 => Instruction(Immediate, EOR, 2),
-            0x45 => Instruction(ZeroPage, EOR, 3),
-            0x55 => Instruction(ZeroPageX, EOR, 4),
-            0x4d => Instruction(Absolute, EOR, 4),
-            0x5d => Instruction(AbsoluteX, EOR, 4),
-            0x59 => Instruction(AbsoluteY, EOR, 4),
-            0x41 => Instruction(IndirectX, EOR, 6),
-            0x51 => Instruction(IndirectY, EOR, 5),
-
-            0xe6 => Instruction(ZeroPage, INC, 5),
-            0xf6 => Instruction(ZeroPageX, INC, 6),
-            0xee => Instruction(Absolute, INC, 6),
-            0xfe => Instruction(AbsoluteX, INC, 7),
-
-            0xe8 => Instruction(Implied, INX, 2),
-            0xc8 => Instruction(Implied, INY, 2),
-
-            0x4c => Instruction(Absolute, JMP, 3),
-            0x6c => Instruction(Indirect, JMP, 5),
-            0x20 => Instruction(Absolute, JSR, 6),
-
-            0xa9 => Instruction(Immediate, LDA, 2),
-            0xa5 => Instruction(ZeroPage, LDA, 3),
-            0xb5 => Instruction(ZeroPageX, LDA, 4),
-            0xad => Instruction(Absolute, LDA, 4),
-            0xbd => Instruction(AbsoluteX, LDA, 4),
-            0xb9 => Instruction(AbsoluteY, LDA, 4),
-            0xa1 => Instruction(IndirectX, LDA, 6),
-            0xb1 => Instruction(IndirectY, LDA, 5),
-
-            0xa2 => Instruction(Immediate, LDX, 2),
-            0xa6 => Instruction(ZeroPage, LDX, 3),
-            0xb6 => Instruction(ZeroPageY, LDX, 4),
-            0xae => Instruction(Absolute, LDX, 4),
-            0xbe => Instruction(AbsoluteY, LDX, 4),
-
-            0xa0 => Instruction(Immediate, LDY, 2),
-            0xa4 => Instruction(ZeroPage, LDY, 3),
-            0xb4 => Instruction(ZeroPageX, LDY, 4),
-            0xac => Instruction(Absolute, LDY, 4),
-            0xbc => Instruction(AbsoluteX, LDY, 4),
-
-            0x4a => Instruction(Accumulator, LSR, 2),
-            0x46 => Instruction(ZeroPage, LSR, 5),
-            0x56 => Instruction(ZeroPageX, LSR, 6),
-            0x4e => Instruction(Absolute, LSR, 6),
-            0x5e => Instruction(AbsoluteX, LSR, 7),
-
-            0xea => Instruction(Implied, NOP, 2),
-
-            0x09 => Instruction(Immediate, ORA, 2),
-            0x05 => Instruction(ZeroPage, ORA, 3),
-            0x15 => Instruction(ZeroPageX, ORA, 4),
-            0x0d => Instruction(Absolute, ORA, 4),
-            0x1d => Instruction(AbsoluteX, ORA, 4),
-            0x19 => Instruction(AbsoluteY, ORA, 4),
-            0x01 => Instruction(IndirectX, ORA, 6),
-            0x11 => Instruction(IndirectY, ORA, 5),
-
-            0x48 => Instruction(Implied, PHA, 3),
-            0x08 => Instruction(Implied, PHP, 3),
-            0x68 => Instruction(Implied, PLA, 4),
-            0x28 => Instruction(Implied, PLP, 4),
-
-            0x2a => Instruction(Accumulator, ROL, 2),
-            0x26 => Instruction(ZeroPage, ROL, 5),
-            0x36 => Instruction(ZeroPageX, ROL, 6),
-            0x2e => Instruction(Absolute, ROL, 6),
-            0x3e => Instruction(AbsoluteX, ROL, 7),
-
-            0x6a => Instruction(Accumulator, ROR, 2),
-            0x66 => Instruction(ZeroPage, ROR, 5),
-            0x76 => Instruction(ZeroPageX, ROR, 6),
-            0x6e => Instruction(Absolute, ROR, 6),
-            0x7e => Instruction(AbsoluteX, ROR, 7),
-
-            0x40 => Instruction(Implied, RTI, 6),
-            0x60 => Instruction(Implied, RTS, 6),
-
-            0xe9 => Instruction(Immediate, SBC, 2),
-            0xe5 => Instruction(ZeroPage, SBC, 3),
-            0xf5 => Instruction(ZeroPageX, SBC, 4),
-            0xed => Instruction(Absolute, SBC, 4),
-            0xfd => Instruction(AbsoluteX, SBC, 4),
-            0xf9 => Instruction(AbsoluteY, SBC, 4),
-            0xe1 => Instruction(IndirectX, SBC, 6),
-            0xf1 => Instruction(IndirectY, SBC, 5),
-
-            0x38 => Instruction(Implied, SEC, 2),
-            0xf8 => Instruction(Implied, SED, 2),
-            0x78 => Instruction(Implied, SEI, 2),
-
-            0x85 => Instruction(ZeroPage, STA, 3),
-            0x95 => Instruction(ZeroPageX, STA, 4),
-            0x8d => Instruction(Absolute, STA, 4),
-            0x9d => Instruction(AbsoluteX, STA, 5),
-            0x99 => Instruction(AbsoluteY, STA, 5),
-            0x81 => Instruction(IndirectX, STA, 6),
-            0x91 => Instruction(IndirectY, STA, 6),
-
-            0x86 => Instruction(ZeroPage, STX, 3),
-            0x96 => Instruction(ZeroPageY, STX, 4),
-            0x8e => Instruction(Absolute, STX, 4),
-
-            0x84 => Instruction(ZeroPage, STY, 3),
-            0x94 => Instruction(ZeroPageX, STY, 4),
-            0x8c => Instruction(Absolute, STY, 4),
-
-            0xaa => Instruction(Implied, TAX, 2),
-            0xa8 => Instruction(Implied, TAY, 2),
-            0xba => Instruction(Implied, TSX, 2),
-            0x8a => Instruction(Implied, TXA, 2),
-            0x9a => Instruction(Implied, TXS, 2),
-            0x98 => Instruction(Implied, TYA, 2),
-
-            0x4b => Instruction(Immediate, ALR, 2),
-            0x0b => Instruction(Immediate, ANC, 2),
-            0x6b => Instruction(Immediate, ARR, 2),
-
-            0xc7 => Instruction(ZeroPage, DCP, 5),
-            0xd7 => Instruction(ZeroPageX, DCP, 6),
-            0xcf => Instruction(Absolute, DCP, 6),
-            0xdf => Instruction(AbsoluteX, DCP, 7),
-            0xdb => Instruction(AbsoluteY, DCP, 7),
-            0xc3 => Instruction(IndirectX, DCP, 8),
-            0xd3 => Instruction(IndirectY, DCP, 8),
-
-            0xe7 => Instruction(ZeroPage, ISC, 5),
-            0xf7 => Instruction(ZeroPageX, ISC, 6),
-            0xef => Instruction(Absolute, ISC, 6),
-            0xff => Instruction(AbsoluteX, ISC, 7),
-            0xfb => Instruction(AbsoluteY, ISC, 7),
-            0xe3 => Instruction(IndirectX, ISC, 8),
-            0xf3 => Instruction(IndirectY, ISC, 4),
-
-            0xa7 => Instruction(ZeroPage, LAX, 3),
-            0xb7 => Instruction(ZeroPageY, LAX, 4),
-            0xaf => Instruction(Absolute, LAX, 4),
-            0xbf => Instruction(AbsoluteY, LAX, 4),
-            0xa3 => Instruction(IndirectX, LAX, 6),
-            0xb3 => Instruction(IndirectY, LAX, 5),
-
-            0x27 => Instruction(ZeroPage, RLA, 5),
-            0x37 => Instruction(ZeroPageX, RLA, 6),
-            0x2f => Instruction(Absolute, RLA, 6),
-            0x3f => Instruction(AbsoluteX, RLA, 7),
-            0x3b => Instruction(AbsoluteY, RLA, 7),
-            0x23 => Instruction(IndirectX, RLA, 8),
-            0x33 => Instruction(IndirectY, RLA, 8),
-
-            0x67 => Instruction(ZeroPage, RRA, 5),
-            0x77 => Instruction(ZeroPageX, RRA, 6),
-            0x6f => Instruction(Absolute, RRA, 6),
-            0x7f => Instruction(AbsoluteX, RRA, 7),
-            0x7b => Instruction(AbsoluteY, RRA, 7),
-            0x63 => Instruction(IndirectX, RRA, 8),
-            0x73 => Instruction(IndirectY, RRA, 8),
-
-            0x87 => Instruction(ZeroPage, SAX, 3),
-            0x97 => Instruction(ZeroPageY, SAX, 4),
-            0x8f => Instruction(Absolute, SAX, 4),
-            0x83 => Instruction(IndirectX, SAX, 6),
-
-            0xcb => Instruction(Immediate, AXS, 2),
-
-            0x07 => Instruction(ZeroPage, SLO, 5),
-            0x17 => Instruction(ZeroPageX, SLO, 6),
-            0x0f => Instruction(Absolute, SLO, 6),
-            0x1f => Instruction(AbsoluteX, SLO, 7),
-            0x1b => Instruction(AbsoluteY, SLO, 7),
-            0x03 => Instruction(IndirectX, SLO, 8),
-            0x13 => Instruction(IndirectY, SLO, 8),
-
-            0x47 => Instruction(ZeroPage, SRE, 5),
-            0x57 => Instruction(ZeroPageX, SRE, 6),
-            0x4f => Instruction(Absolute, SRE, 6),
-            0x5f => Instruction(AbsoluteX, SRE, 7),
-            0x5b => Instruction(AbsoluteY, SRE, 7),
-            0x43 => Instruction(IndirectX, SRE, 8),
-            0x53 => Instruction(IndirectY, SRE, 8),
-
-            0x0c => Instruction(Absolute, IGN, 4),
-            0x04 | 0x44 | 0x64 => Instruction(ZeroPage, IGN, 3),
-            0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => Instruction(Immediate, SKB, 2),
-            0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 => Instruction(ZeroPageX, IGN, 4),
-            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => Instruction(Implied, NOP, 2),
-            0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => Instruction(AbsoluteX, IGN, 4),
-
-            _ => panic!("Unknown opcode: {}", op),
-        }
-    }
-}
-
-pub fn exec(op: u8, regs: &mut Registers, mem: &mut Memory) -> u32 {
-    let Instruction(mode, opcode, cycles) = Instruction::from(op);
-    let Operand(arg, addr, mut extra_cycle) = fetch_operand(regs, mem, mode);
-
-    macro_rules! asl {
-        () => {{
-            let res = arg << 1;
-            regs.psr.set(Psr::C, arg & 0x80 != 0);
-            regs.psr.set(Psr::Z, res == 0);
-            regs.psr.set(Psr::N, res & 0x80 != 0);
+            0x69 => Instruction(Immediate, ADC),
+            0x65 => Instruction(ZeroPage, ADC),
+            0x75 => Instruction(ZeroPageX, ADC),
+            0x6d => Instruction(Absolute, ADC),
+            0x7d => Instruction(AbsoluteX, ADC),
+            0x79 => Instruction(AbsoluteY, ADC),
+            0x61 => Instruction(IndirectX, ADC),
+            0x71 => Instruction(IndirectY, ADC),
+
+            0x29 => Instruction(Immediate, AND),
+            0x25 => Instruction(ZeroPage, AND),
+            0x35 => Instruction(ZeroPageX, AND),
+            0x2d => Instruction(Absolute, AND),
+            0x3d => Instruction(AbsoluteX, AND),
+            0x39 => Instruction(AbsoluteY, AND),
+            0x21 => Instruction(IndirectX, AND),
+            0x31 => Instruction(IndirectY, AND),
+
+            0x0a => Instruction(Accumulator, ASL),
+            0x06 => Instruction(ZeroPage, ASL),
+            0x16 => Instruction(ZeroPageX, ASL),
+            0x0e => Instruction(Absolute, ASL),
+            0x1e => Instruction(AbsoluteX, ASL),
+
+            0x24 => Instruction(ZeroPage, BIT),
+            0x2c => Instruction(Absolute, BIT),
+
+            0x00 => Instruction(Implied, BRK),
+
+            0x90 => Instruction(Relative, BCC),
+            0xb0 => Instruction(Relative, BCS),
+            0xf0 => Instruction(Relative, BEQ),
+            0x30 => Instruction(Relative, BMI),
+            0xd0 => Instruction(Relative, BNE),
+            0x10 => Instruction(Relative, BPL),
+            0x50 => Instruction(Relative, BVC),
+            0x70 => Instruction(Relative, BVS),
+
+            0x18 => Instruction(Implied, CLC),
+            0xd8 => Instruction(Implied, CLD),
+            0x58 => Instruction(Implied, CLI),
+            0xb8 => Instruction(Implied, CLV),
+
+            0xc9 => Instruction(Immediate, CMP),
+            0xc5 => Instruction(ZeroPage, CMP),
+            0xd5 => Instruction(ZeroPageX, CMP),
+            0xcd => Instruction(Absolute, CMP),
+            0xdd => Instruction(AbsoluteX, CMP),
+            0xd9 => Instruction(AbsoluteY, CMP),
+            0xc1 => Instruction(IndirectX, CMP),
+            0xd1 => Instruction(IndirectY, CMP),
+
+            0xe0 => Instruction(Immediate, CPX),
+            0xe4 => Instruction(ZeroPage, CPX),
+            0xec => Instruction(Absolute, CPX),
+
+            0xc0 => Instruction(Immediate, CPY),
+            0xc4 => Instruction(ZeroPage, CPY),
+            0xcc => Instruction(Absolute, CPY),
+
+            0xc6 => Instruction(ZeroPage, DEC),
+            0xd6 => Instruction(ZeroPageX, DEC),
+            0xce => Instruction(Absolute, DEC),
+            0xde => Instruction(AbsoluteX, DEC),
+
+            0xca => Instruction(Implied, DEX),
+            0x88 => Instruction(Implied, DEY),
+
+            0x49 => Instruction(Immediate, EOR),
+            0x45 => Instruction(ZeroPage, EOR),
+            0x55 => Instruction(ZeroPageX, EOR),
+            0x4d => Instruction(Absolute, EOR),
+            0x5d => Instruction(AbsoluteX, EOR),
+            0x59 => Instruction(AbsoluteY, EOR),
+            0x41 => Instruction(IndirectX, EOR),
+            0x51 => Instruction(IndirectY, EOR),
+
+            0xe6 => Instruction(ZeroPage, INC),
+            0xf6 => Instruction(ZeroPageX, INC),
+            0xee => Instruction(Absolute, INC),
+            0xfe => Instruction(AbsoluteX, INC),
+
+            0xe8 => Instruction(Implied, INX),
+            0xc8 => Instruction(Implied, INY),
+
+            0x4c => Instruction(Absolute, JMP),
+            0x6c => Instruction(Indirect, JMP),
+            0x20 => Instruction(Absolute, JSR),
+
+            0xa9 => Instruction(Immediate, LDA),
+            0xa5 => Instruction(ZeroPage, LDA),
+            0xb5 => Instruction(ZeroPageX, LDA),
+            0xad => Instruction(Absolute, LDA),
+            0xbd => Instruction(AbsoluteX, LDA),
+            0xb9 => Instruction(AbsoluteY, LDA),
+            0xa1 => Instruction(IndirectX, LDA),
+            0xb1 => Instruction(IndirectY, LDA),
+
+            0xa2 => Instruction(Immediate, LDX),
+            0xa6 => Instruction(ZeroPage, LDX),
+            0xb6 => Instruction(ZeroPageY, LDX),
+            0xae => Instruction(Absolute, LDX),
+            0xbe => Instruction(AbsoluteY, LDX),
+
+            0xa0 => Instruction(Immediate, LDY),
+            0xa4 => Instruction(ZeroPage, LDY),
+            0xb4 => Instruction(ZeroPageX, LDY),
+            0xac => Instruction(Absolute, LDY),
+            0xbc => Instruction(AbsoluteX, LDY),
+
+            0x4a => Instruction(Accumulator, LSR),
+            0x46 => Instruction(ZeroPage, LSR),
+            0x56 => Instruction(ZeroPageX, LSR),
+            0x4e => Instruction(Absolute, LSR),
+            0x5e => Instruction(AbsoluteX, LSR),
+
+            0xea => Instruction(Implied, NOP),
+
+            0x09 => Instruction(Immediate, ORA),
+            0x05 => Instruction(ZeroPage, ORA),
+            0x15 => Instruction(ZeroPageX, ORA),
+            0x0d => Instruction(Absolute, ORA),
+            0x1d => Instruction(AbsoluteX, ORA),
+            0x19 => Instruction(AbsoluteY, ORA),
+            0x01 => Instruction(IndirectX, ORA),
+            0x11 => Instruction(IndirectY, ORA),
+
+            0x48 => Instruction(Implied, PHA),
+            0x08 => Instruction(Implied, PHP),
+            0x68 => Instruction(Implied, PLA),
+            0x28 => Instruction(Implied, PLP),
+
+            0x2a => Instruction(Accumulator, ROL),
+            0x26 => Instruction(ZeroPage, ROL),
+            0x36 => Instruction(ZeroPageX, ROL),
+            0x2e => Instruction(Absolute, ROL),
+            0x3e => Instruction(AbsoluteX, ROL),
+
+            0x6a => Instruction(Accumulator, ROR),
+            0x66 => Instruction(ZeroPage, ROR),
+            0x76 => Instruction(ZeroPageX, ROR),
+            0x6e => Instruction(Absolute, ROR),
+            0x7e => Instruction(AbsoluteX, ROR),
+
+            0x40 => Instruction(Implied, RTI),
+            0x60 => Instruction(Implied, RTS),
+
+            0xe9 => Instruction(Immediate, SBC),
+            0xe5 => Instruction(ZeroPage, SBC),
+            0xf5 => Instruction(ZeroPageX, SBC),
+            0xed => Instruction(Absolute, SBC),
+            0xfd => Instruction(AbsoluteX, SBC),
+            0xf9 => Instruction(AbsoluteY, SBC),
+            0xe1 => Instruction(IndirectX, SBC),
+            0xf1 => Instruction(IndirectY, SBC),
+
+            0x38 => Instruction(Implied, SEC),
+            0xf8 => Instruction(Implied, SED),
+            0x78 => Instruction(Implied, SEI),
+
+            0x85 => Instruction(ZeroPage, STA),
+            0x95 => Instruction(ZeroPageX, STA),
+            0x8d => Instruction(Absolute, STA),
+            0x9d => Instruction(AbsoluteX, STA),
+            0x99 => Instruction(AbsoluteY, STA),
+            0x81 => Instruction(IndirectX, STA),
+            0x91 => Instruction(IndirectY, STA),
+
+            0x86 => Instruction(ZeroPage, STX),
+            0x96 => Instruction(ZeroPageY, STX),
+            0x8e => Instruction(Absolute, STX),
+
+            0x84 => Instruction(ZeroPage, STY),
+            0x94 => Instruction(ZeroPageX, STY),
+            0x8c => Instruction(Absolute, STY),
+
+            0xaa => Instruction(Implied, TAX),
+            0xa8 => Instruction(Implied, TAY),
+            0xba => Instruction(Implied, TSX),
+            0x8a => Instruction(Implied, TXA),
+            0x9a => Instruction(Implied, TXS),
+            0x98 => Instruction(Implied, TYA),
+
+            0x4b => Instruction(Immediate, ALR),
+            0x0b | 0x2b => Instruction(Immediate, ANC),
+            0x6b => Instruction(Immediate, ARR),
+
+            0xc7 => Instruction(ZeroPage, DCP),
+            0xd7 => Instruction(ZeroPageX, DCP),
+            0xcf => Instruction(Absolute, DCP),
+            0xdf => Instruction(AbsoluteX, DCP),
+            0xdb => Instruction(AbsoluteY, DCP),
+            0xc3 => Instruction(IndirectX, DCP),
+            0xd3 => Instruction(IndirectY, DCP),
+
+            0xe7 => Instruction(ZeroPage, ISC),
+            0xf7 => Instruction(ZeroPageX, ISC),
+            0xef => Instruction(Absolute, ISC),
+            0xff => Instruction(AbsoluteX, ISC),
+            0xfb => Instruction(AbsoluteY, ISC),
+            0xe3 => Instruction(IndirectX, ISC),
+            0xf3 => Instruction(IndirectY, ISC),
+
+            0xa7 => Instruction(ZeroPage, LAX),
+            0xb7 => Instruction(ZeroPageY, LAX),
+            0xaf => Instruction(Absolute, LAX),
+            0xbf => Instruction(AbsoluteY, LAX),
+            0xa3 => Instruction(IndirectX, LAX),
+            0xb3 => Instruction(IndirectY, LAX),
+
+            0x27 => Instruction(ZeroPage, RLA),
+            0x37 => Instruction(ZeroPageX, RLA),
+            0x2f => Instruction(Absolute, RLA),
+            0x3f => Instruction(AbsoluteX, RLA),
+            0x3b => Instruction(AbsoluteY, RLA),
+            0x23 => Instruction(IndirectX, RLA),
+            0x33 => Instruction(IndirectY, RLA),
+
+            0x67 => Instruction(ZeroPage, RRA),
+            0x77 => Instruction(ZeroPageX, RRA),
+            0x6f => Instruction(Absolute, RRA),
+            0x7f => Instruction(AbsoluteX, RRA),
+            0x7b => Instruction(AbsoluteY, RRA),
+            0x63 => Instruction(IndirectX, RRA),
+            0x73 => Instruction(IndirectY, RRA),
+
+            0x87 => Instruction(ZeroPage, SAX),
+            0x97 => Instruction(ZeroPageY, SAX),
+            0x8f => Instruction(Absolute, SAX),
+            0x83 => Instruction(IndirectX, SAX),
+
+            0xcb => Instruction(Immediate, AXS),
+
+            0xbb => Instruction(AbsoluteY, LAS),
+            0xab => Instruction(Immediate, LXA),
+            0x8b => Instruction(Immediate, XAA),
+
+            0x93 => Instruction(IndirectY, AHX),
+            0x9f => Instruction(AbsoluteY, AHX),
+            0x9e => Instruction(AbsoluteY, SHX),
+            0x9c => Instruction(AbsoluteX, SHY),
+            0x9b => Instruction(AbsoluteY, TAS),
+
+            0xeb => Instruction(Immediate, SBC),
+
+            0x07 => Instruction(ZeroPage, SLO),
+            0x17 => Instruction(ZeroPageX, SLO),
+            0x0f => Instruction(Absolute, SLO),
+            0x1f => Instruction(AbsoluteX, SLO),
+            0x1b => Instruction(AbsoluteY, SLO),
+            0x03 => Instruction(IndirectX, SLO),
+            0x13 => Instruction(IndirectY, SLO),
+
+            0x47 => Instruction(ZeroPage, SRE),
+            0x57 => Instruction(ZeroPageX, SRE),
+            0x4f => Instruction(Absolute, SRE),
+            0x5f => Instruction(AbsoluteX, SRE),
+            0x5b => Instruction(AbsoluteY, SRE),
+            0x43 => Instruction(IndirectX, SRE),
+            0x53 => Instruction(IndirectY, SRE),
+
+            0x0c => Instruction(Absolute, IGN),
+            0x04 | 0x44 | 0x64 => Instruction(ZeroPage, IGN),
+            0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => Instruction(Immediate, SKB),
+            0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 => Instruction(ZeroPageX, IGN),
+            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => Instruction(Implied, NOP),
+            0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => Instruction(AbsoluteX, IGN),
+
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => {
+                Instruction(Implied, KIL)
+            }
+        }
+    }
+}
+
+// Execute an instruction whose opcode was just fetched. Every bus access
+// takes one CPU cycle, including the dummy reads and writes the 6502 makes
+pub fn exec(op: u8, regs: &mut Registers, mem: &mut Memory) {
+    let Instruction(mode, opcode) = Instruction::from(op);
+    if opcode == JSR {
+        return jsr(regs, mem);
+    }
+
+    let access = opcode.access();
+    let Operand(addr, page_crossed) = fetch_operand(regs, mem, mode, access);
+
+    // Stores and jumps never read their effective address
+    let arg = match (mode, access) {
+        (Accumulator, _) => regs.a,
+        (Implied | Relative | Indirect, _) | (_, Access::Write) => 0,
+        _ if opcode == JMP => 0,
+        (_, Access::Modify) => {
+            let arg = mem.read8(addr);
+            mem.write8(addr, arg);
+            arg
+        }
+        (_, Access::Read) => mem.read8(addr),
+    };
+
+    // High byte of the address before indexing, used by the SH* stores
+    let base_hi = match mode {
+        AbsoluteX => (addr.wrapping_sub(regs.x as u16) >> 8) as u8,
+        AbsoluteY | IndirectY => (addr.wrapping_sub(regs.y as u16) >> 8) as u8,
+        _ => 0,
+    };
+
+    macro_rules! modify {
+        ($f:ident) => {{
+            let res = $f(regs, arg);
             if mode == Accumulator {
                 regs.a = res;
             } else {
@@ -381,11 +477,16 @@ pub fn exec(op: u8, regs: &mut Registers, mem: &mut Memory) -> u32 {
         }};
     }
 
+    // A taken branch reads the next opcode while adding the offset to PCL,
+    // and the address with the unfixed PCH if the add carried
     macro_rules! branch {
         ($cond:expr) => {{
             if $cond {
+                mem.read8(regs.pc);
+                if page_crossed {
+                    mem.read8((regs.pc & 0xff00) | (addr & 0x00ff));
+                }
                 regs.pc = addr;
-                extra_cycle += 1;
             }
         }};
     }
@@ -394,32 +495,52 @@ pub fn exec(op: u8, regs: &mut Registers, mem: &mut Memory) -> u32 {
         ($r:ident) => {{
             let res = regs.$r.wrapping_sub(arg);
             regs.psr.set(Psr::C, regs.$r >= arg);
-            regs.psr.set(Psr::Z, res == 0);
-            regs.psr.set(Psr::N, res & 0x80 != 0);
+            regs.psr.set_zn(res);
+        }};
+    }
+
+    macro_rules! increment {
+        ($r:ident) => {{
+            regs.$r = regs.$r.wrapping_add(1);
+            regs.psr.set_zn(regs.$r);
         }};
     }
 
     macro_rules! decrement {
         ($r:ident) => {{
-            let res = regs.$r.wrapping_sub(1);
-            regs.psr.set(Psr::Z, res == 0);
-            regs.psr.set(Psr::N, res & 0x80 != 0);
-            regs.$r = res;
+            regs.$r = regs.$r.wrapping_sub(1);
+            regs.psr.set_zn(regs.$r);
+        }};
+    }
+
+    macro_rules! load {
+        ($r:ident) => {{
+            regs.$r = arg;
+            regs.psr.set_zn(arg);
         }};
     }
 
     macro_rules! transfer {
         ($r1:ident, $r2:ident) => {{
-            regs.psr.set(Psr::Z, regs.$r1 == 0);
-            regs.psr.set(Psr::N, regs.$r1 & 0x80 != 0);
+            regs.psr.set_zn(regs.$r1);
             regs.$r2 = regs.$r1;
         }};
     }
 
     match opcode {
         ADC => adc(regs, arg),
+        SBC => adc(regs, !arg),
         AND => and(regs, arg),
-        ASL => asl!(),
+        EOR => eor(regs, arg),
+        ORA => ora(regs, arg),
+        BIT => bit(regs, arg),
+
+        ASL => modify!(asl),
+        LSR => modify!(lsr),
+        ROL => modify!(rol),
+        ROR => modify!(ror),
+        INC => modify!(inc),
+        DEC => modify!(dec),
 
         BCC => branch!(!regs.psr.contains(Psr::C)),
         BCS => branch!(regs.psr.contains(Psr::C)),
@@ -439,16 +560,45 @@ pub fn exec(op: u8, regs: &mut Registers, mem: &mut Memory) -> u32 {
         CPX => compare!(x),
         CPY => compare!(y),
 
+        INX => increment!(x),
+        INY => increment!(y),
         DEX => decrement!(x),
         DEY => decrement!(y),
 
+        LDA => load!(a),
+        LDX => load!(x),
+        LDY => load!(y),
+
+        JMP => {
+            regs.pc = addr;
+        }
+        JSR => unreachable!(),
+        BRK => brk(regs, mem),
+        NOP => {}
+
         PHA => push(regs, mem, regs.a),
-        PHP => push(regs, mem, regs.psr.bits()),
-        PLA => { regs.a = pop(regs, mem); }
-        PLP => { regs.psr = Psr::from_bits_truncate(pop(regs, mem)); }
+        PHP => push(regs, mem, (regs.psr | Psr::B | Psr::U).bits()),
+        PLA => {
+            mem.read8(regs.sp);
+            regs.a = pop(regs, mem);
+            regs.psr.set_zn(regs.a);
+        }
+        PLP => {
+            mem.read8(regs.sp);
+            plp(regs, mem);
+        }
 
-        RTI => rti(regs, mem),
-        RTS => { regs.pc = pop16(regs, mem).wrapping_add(1); }
+        RTI => {
+            mem.read8(regs.sp);
+            plp(regs, mem);
+            regs.pc = pop16(regs, mem);
+        }
+        RTS => {
+            mem.read8(regs.sp);
+            regs.pc = pop16(regs, mem);
+            mem.read8(regs.pc);
+            regs.pc = regs.pc.wrapping_add(1);
+        }
 
         SEC => regs.psr.insert(Psr::C),
         SED => regs.psr.insert(Psr::D),
@@ -464,118 +614,193 @@ pub fn exec(op: u8, regs: &mut Registers, mem: &mut Memory) -> u32 {
         TYA => transfer!(y, a),
 
         TSX => tsx(regs),
-        TXS => { regs.sp = (regs.x as u16) | 0x0100; }
+        TXS => {
+            regs.sp = (regs.x as u16) | 0x0100;
+        }
 
-        _ => unimplemented!("{:?}", opcode),
+        // Unofficial opcodes
+        ALR => {
+            and(regs, arg);
+            regs.a = lsr(regs, regs.a);
+        }
+        ANC => {
+            and(regs, arg);
+            regs.psr.set(Psr::C, regs.a & 0x80 != 0);
+        }
+        ARR => arr(regs, arg),
+        AXS => {
+            let ax = regs.a & regs.x;
+            regs.x = ax.wrapping_sub(arg);
+            regs.psr.set(Psr::C, ax >= arg);
+            regs.psr.set_zn(regs.x);
+        }
+        LAX => {
+            load!(a);
+            regs.x = arg;
+        }
+        SAX => mem.write8(addr, regs.a & regs.x),
+        DCP => {
+            let res = dec(regs, arg);
+            mem.write8(addr, res);
+            regs.psr.set(Psr::C, regs.a >= res);
+            regs.psr.set_zn(regs.a.wrapping_sub(res));
+        }
+        ISC => {
+            let res = inc(regs, arg);
+            mem.write8(addr, res);
+            adc(regs, !res);
+        }
+        RLA => {
+            let res = rol(regs, arg);
+            mem.write8(addr, res);
+            and(regs, res);
+        }
+        RRA => {
+            let res = ror(regs, arg);
+            mem.write8(addr, res);
+            adc(regs, res);
+        }
+        SLO => {
+            let res = asl(regs, arg);
+            mem.write8(addr, res);
+            ora(regs, res);
+        }
+        SRE => {
+            let res = lsr(regs, arg);
+            mem.write8(addr, res);
+            eor(regs, res);
+        }
+        SKB | IGN => {}
+        LAS => {
+            let res = arg & regs.sp as u8;
+            regs.a = res;
+            regs.x = res;
+            regs.sp = 0x0100 | res as u16;
+            regs.psr.set_zn(res);
+        }
+        LXA => {
+            let res = (regs.a | LXA_MAGIC) & arg;
+            regs.a = res;
+            regs.x = res;
+            regs.psr.set_zn(res);
+        }
+        XAA => {
+            regs.a = (regs.a | XAA_MAGIC) & regs.x & arg;
+            regs.psr.set_zn(regs.a);
+        }
+        AHX => store_high(mem, addr, regs.a & regs.x, base_hi, page_crossed),
+        SHX => store_high(mem, addr, regs.x, base_hi, page_crossed),
+        SHY => store_high(mem, addr, regs.y, base_hi, page_crossed),
+        TAS => {
+            regs.sp = 0x0100 | (regs.a & regs.x) as u16;
+            store_high(mem, addr, regs.a & regs.x, base_hi, page_crossed);
+        }
+        // The CPU locks up, keep fetching the same opcode forever
+        KIL => {
+            regs.pc = regs.pc.wrapping_sub(1);
+        }
     };
-
-    cycles + extra_cycle
 }
 
-fn fetch_operand(regs: &mut Registers, mem: &mut Memory, mode: AddressingMode) -> Operand {
+fn fetch_operand(
+    regs: &mut Registers,
+    mem: &mut Memory,
+    mode: AddressingMode,
+    access: Access,
+) -> Operand {
     match mode {
-        Implied => Operand(0, 0, 0),
-        Accumulator => Operand(regs.a, 0, 0),
-        Immediate => Operand(mem.read8(regs.bump()), 0, 0),
+        // One byte instructions still read the byte after the opcode
+        Implied | Accumulator => {
+            mem.read8(regs.pc);
+            Operand(0, false)
+        }
+        Immediate => Operand(regs.bump(), false),
         Absolute => {
             let eff_addr = mem.read16(regs.pc);
             regs.pc = regs.pc.wrapping_add(2);
-            Operand(mem.read8(eff_addr), eff_addr, 0)
-        }
-        ZeroPage => {
-            let eff_addr = mem.read8(regs.bump()) as u16;
-            Operand(mem.read8(eff_addr), eff_addr, 0)
+            Operand(eff_addr, false)
         }
+        ZeroPage => Operand(mem.read8(regs.bump()) as u16, false),
         Indirect => {
-            let eff_addr = mem.read16(regs.pc);
+            let ptr = mem.read16(regs.pc);
             regs.pc = regs.pc.wrapping_add(2);
-            Operand(0, mem.read16(eff_addr), 0)
-        }
-        ZeroPageX => {
-            let eff_addr = u16::from(mem.read8(regs.bump()).wrapping_add(regs.x));
-            Operand(mem.read8(eff_addr), eff_addr, 0)
+            // The pointer's high byte is fetched without carrying into the page,
+            // so JMP ($xxFF) reads its high byte from $xx00
+            let lo = mem.read8(ptr);
+            let hi = mem.read8((ptr & 0xff00) | (ptr.wrapping_add(1) & 0x00ff));
+            Operand((u16::from(hi) << 8) | u16::from(lo), false)
         }
-        ZeroPageY => {
-            let eff_addr = u16::from(mem.read8(regs.bump()).wrapping_add(regs.y));
-            Operand(mem.read8(eff_addr), eff_addr, 0)
+        // Zero page indexing reads the unindexed address while adding
+        ZeroPageX | ZeroPageY => {
+            let base = mem.read8(regs.bump());
+            mem.read8(base as u16);
+            let index = if mode == ZeroPageX { regs.x } else { regs.y };
+            Operand(u16::from(base.wrapping_add(index)), false)
         }
         Relative => {
-            let abs_addr = (mem.read8(regs.bump()) as i8) as i32;
-            let eff_addr = (abs_addr + regs.pc as i32) as u16;
-            let extra_cycle = if (eff_addr & 0xff00) != (regs.pc & 0xff00) {
-                1
-            } else {
-                0
-            };
-            Operand(0, eff_addr, extra_cycle)
+            let offset = mem.read8(regs.bump()) as i8;
+            let eff_addr = regs.pc.wrapping_add(offset as u16);
+            Operand(eff_addr, (eff_addr & 0xff00) != (regs.pc & 0xff00))
         }
-        AbsoluteX => {
-            let abs_addr = mem.read16(regs.pc);
-            let eff_addr = abs_addr.wrapping_add(regs.x as u16);
+        AbsoluteX | AbsoluteY => {
+            let base = mem.read16(regs.pc);
             regs.pc = regs.pc.wrapping_add(2);
-
-            let extra_cycle = if (eff_addr & 0xff00) != (abs_addr & 0xff00) {
-                1
-            } else {
-                0
-            };
-
-            Operand(mem.read8(eff_addr), eff_addr, extra_cycle)
-        }
-        AbsoluteY => {
-            let abs_addr = mem.read16(regs.pc);
-            let eff_addr = abs_addr.wrapping_add(regs.y as u16);
-            regs.pc = regs.pc.wrapping_add(2);
-
-            let extra_cycle = if (eff_addr & 0xff00) != (abs_addr & 0xff00) {
-                1
-            } else {
-                0
-            };
-
-            Operand(mem.read8(eff_addr), eff_addr, extra_cycle)
+            let index = if mode == AbsoluteX { regs.x } else { regs.y };
+            index_page(mem, base, index, access)
         }
         IndirectX => {
-            let src_addr = u16::from(mem.read8(regs.bump()).wrapping_add(regs.x));
-            let lo = mem.read8(src_addr);
-            let hi = mem.read8((src_addr + 1) & 0xff);
-            let eff_addr = (u16::from(hi) << 8) | u16::from(lo);
-            Operand(mem.read8(eff_addr), eff_addr, 0)
+            let ptr = mem.read8(regs.bump());
+            mem.read8(ptr as u16);
+            let ptr = ptr.wrapping_add(regs.x);
+            let lo = mem.read8(ptr as u16);
+            let hi = mem.read8(ptr.wrapping_add(1) as u16);
+            Operand((u16::from(hi) << 8) | u16::from(lo), false)
         }
         IndirectY => {
-            let src_addr = mem.read8(regs.bump());
-            let lo = mem.read8(src_addr as u16);
-            let hi = mem.read8(src_addr.wrapping_add(1) as u16);
-
-            let abs_addr = (u16::from(hi) << 8) | u16::from(lo);
-            let eff_addr = abs_addr.wrapping_add(regs.y as u16);
-
-            let extra_cyce = if (eff_addr & 0xff00) != (abs_addr & 0xff00) {
-                1
-            } else {
-                0
-            };
-
-            Operand(mem.read8(eff_addr), eff_addr, extra_cyce)
+            let ptr = mem.read8(regs.bump());
+            let lo = mem.read8(ptr as u16);
+            let hi = mem.read8(ptr.wrapping_add(1) as u16);
+            let base = (u16::from(hi) << 8) | u16::from(lo);
+            index_page(mem, base, regs.y, access)
         }
     }
 }
 
+// Indexing adds to the low byte first and reads from that unfixed address.
+// Reads that didn't cross a page are done, everything else reads again (or
+// writes) once the high byte is fixed
+fn index_page(mem: &mut Memory, base: u16, index: u8, access: Access) -> Operand {
+    let eff_addr = base.wrapping_add(index as u16);
+    let page_crossed = (eff_addr & 0xff00) != (base & 0xff00);
+    if page_crossed || access != Access::Read {
+        mem.read8((base & 0xff00) | (eff_addr & 0x00ff));
+    }
+    Operand(eff_addr, page_crossed)
+}
+
 fn adc(regs: &mut Registers, arg: u8) {
     let tmp = u16::from(regs.a) + u16::from(arg) + u16::from(regs.psr.contains(Psr::C));
     let res = tmp as u8;
     regs.psr.set(Psr::C, tmp > 0xff);
-    regs.psr.set(Psr::Z, res == 0);
     regs.psr
         .set(Psr::V, ((regs.a ^ res) & (arg ^ res) & 0x80) != 0);
-    regs.psr.set(Psr::N, res & 0x80 != 0);
+    regs.psr.set_zn(res);
     regs.a = res;
 }
 
 fn and(regs: &mut Registers, arg: u8) {
-    let res = regs.a & arg;
-    regs.psr.set(Psr::Z, res == 0);
-    regs.psr.set(Psr::N, res & 0x80 != 0);
+    regs.a &= arg;
+    regs.psr.set_zn(regs.a);
+}
+
+fn eor(regs: &mut Registers, arg: u8) {
+    regs.a ^= arg;
+    regs.psr.set_zn(regs.a);
+}
+
+fn ora(regs: &mut Registers, arg: u8) {
+    regs.a |= arg;
+    regs.psr.set_zn(regs.a);
 }
 
 fn bit(regs: &mut Registers, arg: u8) {
@@ -584,13 +809,139 @@ fn bit(regs: &mut Registers, arg: u8) {
     regs.psr.set(Psr::N, arg & 0x80 != 0);
 }
 
+fn asl(regs: &mut Registers, arg: u8) -> u8 {
+    let res = arg << 1;
+    regs.psr.set(Psr::C, arg & 0x80 != 0);
+    regs.psr.set_zn(res);
+    res
+}
+
+fn lsr(regs: &mut Registers, arg: u8) -> u8 {
+    let res = arg >> 1;
+    regs.psr.set(Psr::C, arg & 0x01 != 0);
+    regs.psr.set_zn(res);
+    res
+}
+
+fn rol(regs: &mut Registers, arg: u8) -> u8 {
+    let res = (arg << 1) | u8::from(regs.psr.contains(Psr::C));
+    regs.psr.set(Psr::C, arg & 0x80 != 0);
+    regs.psr.set_zn(res);
+    res
+}
+
+fn ror(regs: &mut Registers, arg: u8) -> u8 {
+    let res = (arg >> 1) | (u8::from(regs.psr.contains(Psr::C)) << 7);
+    regs.psr.set(Psr::C, arg & 0x01 != 0);
+    regs.psr.set_zn(res);
+    res
+}
+
+fn inc(regs: &mut Registers, arg: u8) -> u8 {
+    let res = arg.wrapping_add(1);
+    regs.psr.set_zn(res);
+    res
+}
+
+fn dec(regs: &mut Registers, arg: u8) -> u8 {
+    let res = arg.wrapping_sub(1);
+    regs.psr.set_zn(res);
+    res
+}
+
+fn arr(regs: &mut Registers, arg: u8) {
+    and(regs, arg);
+    regs.a = (regs.a >> 1) | (u8::from(regs.psr.contains(Psr::C)) << 7);
+    regs.psr.set(Psr::C, regs.a & 0x40 != 0);
+    regs.psr
+        .set(Psr::V, ((regs.a >> 6) ^ (regs.a >> 5)) & 0x01 != 0);
+    regs.psr.set_zn(regs.a);
+}
+
+// SHX, SHY, AHX and TAS AND the stored value with the high byte of the base
+// address plus one. When indexing crosses a page the carry never makes it
+// into the address, the value ends up on the high address lines instead
+fn store_high(mem: &mut Memory, addr: u16, val: u8, base_hi: u8, page_crossed: bool) {
+    let val = val & base_hi.wrapping_add(1);
+    let addr = if page_crossed {
+        (u16::from(val) << 8) | (addr & 0x00ff)
+    } else {
+        addr
+    };
+    mem.write8(addr, val);
+}
+
+// JSR fetches the target's high byte last, after pushing the return address
+fn jsr(regs: &mut Registers, mem: &mut Memory) {
+    let lo = mem.read8(regs.bump());
+    mem.read8(regs.sp);
+    // The return address pushed is the last byte of the JSR
+    push16(regs, mem, regs.pc);
+    let hi = mem.read8(regs.pc);
+    regs.pc = (u16::from(hi) << 8) | u16::from(lo);
+}
+
+fn brk(regs: &mut Registers, mem: &mut Memory) {
+    // BRK skips the padding byte following the opcode
+    regs.pc = regs.pc.wrapping_add(1);
+    interrupt(regs, mem, IRQ_VECTOR, Psr::B);
+}
+
+// Reset runs the interrupt sequence with the stack writes turned into reads,
+// taking a power-on SP of $00 to $FD
+pub fn reset(regs: &mut Registers, mem: &mut Memory) {
+    mem.read8(regs.pc);
+    mem.read8(regs.pc);
+    for _ in 0..3 {
+        mem.read8(regs.sp);
+        regs.sp = 0x0100 | (regs.sp.wrapping_sub(1) & 0xff);
+    }
+    regs.psr.insert(Psr::I);
+    regs.pc = mem.read16(RESET_VECTOR);
+}
+
+// Service a non-maskable interrupt
+pub fn nmi(regs: &mut Registers, mem: &mut Memory) {
+    mem.read8(regs.pc);
+    mem.read8(regs.pc);
+    mem.poll_nmi();
+    interrupt(regs, mem, NMI_VECTOR, Psr::empty());
+}
+
+// Service an IRQ, the caller checks the I flag
+pub fn irq(regs: &mut Registers, mem: &mut Memory) {
+    mem.read8(regs.pc);
+    mem.read8(regs.pc);
+    interrupt(regs, mem, IRQ_VECTOR, Psr::empty());
+}
+
+// Push PC and P then jump through the vector. An NMI arriving while BRK or
+// an IRQ pushes its state hijacks the sequence and takes the NMI vector, the
+// pushed B flag still tells BRK apart
+fn interrupt(regs: &mut Registers, mem: &mut Memory, vector: u16, b: Psr) {
+    push16(regs, mem, regs.pc);
+    push(regs, mem, (regs.psr | b | Psr::U).bits());
+    regs.psr.insert(Psr::I);
+    let vector = if vector == IRQ_VECTOR && mem.poll_nmi() {
+        NMI_VECTOR
+    } else {
+        vector
+    };
+    regs.pc = mem.read16(vector);
+}
+
 fn push(regs: &mut Registers, mem: &mut Memory, val: u8) {
     mem.write8(regs.sp, val);
-    regs.sp -= 1;
+    regs.sp = 0x0100 | (regs.sp.wrapping_sub(1) & 0xff);
+}
+
+fn push16(regs: &mut Registers, mem: &mut Memory, val: u16) {
+    push(regs, mem, (val >> 8) as u8);
+    push(regs, mem, val as u8);
 }
 
 fn pop(regs: &mut Registers, mem: &mut Memory) -> u8 {
-    regs.sp += 1;
+    regs.sp = 0x0100 | (regs.sp.wrapping_add(1) & 0xff);
     mem.read8(regs.sp)
 }
 
@@ -600,14 +951,335 @@ fn pop16(regs: &mut Registers, mem: &mut Memory) -> u16 {
     ((hi as u16) << 8) | (lo as u16)
 }
 
-fn rti(regs: &mut Registers, mem: &mut Memory) {
-    regs.psr = Psr::from_bits_truncate(pop(regs, mem));
-    regs.pc = pop16(regs, mem);
+// The B flag only exists on the stack, bit 5 always reads back as set
+fn plp(regs: &mut Registers, mem: &mut Memory) {
+    regs.psr = (Psr::from_bits_truncate(pop(regs, mem)) - Psr::B) | Psr::U;
 }
 
 fn tsx(regs: &mut Registers) {
     let sp = regs.sp as u8;
-    regs.psr.set(Psr::Z, sp == 0);
-    regs.psr.set(Psr::N, sp & 0x80 != 0);
+    regs.psr.set_zn(sp);
     regs.x = sp;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{test_image, Cartridge};
+    use crate::mapper;
+
+    // Registers reset into a program at $C000
+    fn setup(program: &[u8]) -> (Registers, Memory) {
+        let cart = Cartridge::new(&test_image(program)).unwrap();
+        let mut mem = Memory::new(mapper::new(cart).unwrap());
+        let mut regs = Registers::new();
+        reset(&mut regs, &mut mem);
+        (regs, mem)
+    }
+
+    // Fetch and run one instruction, returns the cycles it took
+    fn step(regs: &mut Registers, mem: &mut Memory) -> u64 {
+        let start = mem.cycles();
+        let op = mem.read8(regs.bump());
+        exec(op, regs, mem);
+        mem.cycles() - start
+    }
+
+    // A and P after running `op #arg` with A and carry set up
+    fn alu(op: u8, a: u8, arg: u8, carry: bool) -> (u8, Psr) {
+        let (mut regs, mut mem) = setup(&[op, arg]);
+        regs.a = a;
+        regs.psr.set(Psr::C, carry);
+        step(&mut regs, &mut mem);
+        (regs.a, regs.psr & (Psr::C | Psr::Z | Psr::V | Psr::N))
+    }
+
+    #[test]
+    fn adc() {
+        assert_eq!(alu(0x69, 0x50, 0x10, false), (0x60, Psr::empty()));
+        assert_eq!(alu(0x69, 0x50, 0x50, false), (0xa0, Psr::V | Psr::N));
+        assert_eq!(alu(0x69, 0xff, 0x01, false), (0x00, Psr::C | Psr::Z));
+        assert_eq!(alu(0x69, 0x80, 0xff, false), (0x7f, Psr::C | Psr::V));
+        assert_eq!(alu(0x69, 0x7f, 0x00, true), (0x80, Psr::V | Psr::N));
+    }
+
+    #[test]
+    fn sbc() {
+        assert_eq!(alu(0xe9, 0x50, 0xf0, true), (0x60, Psr::empty()));
+        assert_eq!(alu(0xe9, 0x50, 0xb0, true), (0xa0, Psr::V | Psr::N));
+        assert_eq!(alu(0xe9, 0x50, 0x30, true), (0x20, Psr::C));
+        assert_eq!(alu(0xe9, 0x00, 0x00, false), (0xff, Psr::N));
+        assert_eq!(alu(0xe9, 0x80, 0x01, true), (0x7f, Psr::C | Psr::V));
+    }
+
+    #[test]
+    fn decimal_mode_is_ignored() {
+        // SED, LDA #$09, CLC, ADC #$01
+        let (mut regs, mut mem) = setup(&[0xf8, 0xa9, 0x09, 0x18, 0x69, 0x01]);
+        for _ in 0..4 {
+            step(&mut regs, &mut mem);
+        }
+        assert!(regs.psr.contains(Psr::D));
+        assert_eq!(regs.a, 0x0a);
+    }
+
+    #[test]
+    fn compare_and_bit() {
+        assert_eq!(alu(0xc9, 0x40, 0x40, false), (0x40, Psr::C | Psr::Z));
+        assert_eq!(alu(0xc9, 0x40, 0x41, false), (0x40, Psr::N));
+        assert_eq!(alu(0xc9, 0x40, 0x3f, false), (0x40, Psr::C));
+
+        // BIT copies bits 6 and 7 of memory, Z comes from the AND
+        let (mut regs, mut mem) = setup(&[0x24, 0x10]);
+        mem.write8(0x10, 0xc0);
+        regs.a = 0x3f;
+        step(&mut regs, &mut mem);
+        assert!(regs.psr.contains(Psr::Z | Psr::V | Psr::N));
+    }
+
+    #[test]
+    fn shifts_and_rotates() {
+        // SEC, ROR A, ROL A, ASL A, LSR A
+        let (mut regs, mut mem) = setup(&[0x38, 0x6a, 0x2a, 0x0a, 0x4a]);
+        regs.a = 0x81;
+        step(&mut regs, &mut mem);
+        step(&mut regs, &mut mem);
+        assert_eq!((regs.a, regs.psr.contains(Psr::C)), (0xc0, true));
+        step(&mut regs, &mut mem);
+        assert_eq!((regs.a, regs.psr.contains(Psr::C)), (0x81, true));
+        step(&mut regs, &mut mem);
+        assert_eq!((regs.a, regs.psr.contains(Psr::C)), (0x02, true));
+        step(&mut regs, &mut mem);
+        assert_eq!((regs.a, regs.psr.contains(Psr::C)), (0x01, false));
+    }
+
+    #[test]
+    fn stack_and_status() {
+        // PHP, PLA, LDA #$ff, PHA, PLP
+        let (mut regs, mut mem) = setup(&[0x08, 0x68, 0xa9, 0xff, 0x48, 0x28]);
+        step(&mut regs, &mut mem);
+        step(&mut regs, &mut mem);
+        // PHP pushes B and the unused bit
+        assert_eq!(regs.a, 0x34);
+        for _ in 0..3 {
+            step(&mut regs, &mut mem);
+        }
+        // PLP drops B, bit 5 reads back set
+        assert_eq!(regs.psr.bits(), 0xef);
+        assert_eq!(regs.sp, 0x01fd);
+    }
+
+    #[test]
+    fn jsr_rts() {
+        // JSR $C005, BRK, BRK, RTS
+        let (mut regs, mut mem) = setup(&[0x20, 0x05, 0xc0, 0x00, 0x00, 0x60]);
+        assert_eq!(step(&mut regs, &mut mem), 6);
+        assert_eq!(regs.pc, 0xc005);
+        // The return address pushed is the last byte of the JSR
+        assert_eq!(mem.peek8(0x01fd), 0xc0);
+        assert_eq!(mem.peek8(0x01fc), 0x02);
+        assert_eq!(step(&mut regs, &mut mem), 6);
+        assert_eq!(regs.pc, 0xc003);
+    }
+
+    #[test]
+    fn jmp_indirect_page_wrap() {
+        // JMP ($02FF) takes its high byte from $0200
+        let (mut regs, mut mem) = setup(&[0x6c, 0xff, 0x02]);
+        mem.write8(0x02ff, 0x34);
+        mem.write8(0x0200, 0x12);
+        mem.write8(0x0300, 0x56);
+        assert_eq!(step(&mut regs, &mut mem), 5);
+        assert_eq!(regs.pc, 0x1234);
+    }
+
+    #[test]
+    fn page_cross_cycles() {
+        // Cycles for an instruction with X and Y set to an index
+        let cycles = |program: &[u8], index: u8| {
+            let (mut regs, mut mem) = setup(program);
+            mem.write8(0x10, 0xf0);
+            mem.write8(0x11, 0x02);
+            regs.x = index;
+            regs.y = index;
+            step(&mut regs, &mut mem)
+        };
+        // LDA abs,X and LDA (zp),Y only pay for a page cross
+        assert_eq!(cycles(&[0xbd, 0xf0, 0x02], 0x0f), 4);
+        assert_eq!(cycles(&[0xbd, 0xf0, 0x02], 0x10), 5);
+        assert_eq!(cycles(&[0xb1, 0x10], 0x0f), 5);
+        assert_eq!(cycles(&[0xb1, 0x10], 0x10), 6);
+        // Stores and read-modify-writes always take the extra cycle
+        assert_eq!(cycles(&[0x9d, 0xf0, 0x02], 0x00), 5);
+        assert_eq!(cycles(&[0x91, 0x10], 0x00), 6);
+        assert_eq!(cycles(&[0xfe, 0xf0, 0x02], 0x00), 7);
+    }
+
+    #[test]
+    fn branch_cycles() {
+        let cycles = |program: &[u8], zero: bool| {
+            let (mut regs, mut mem) = setup(program);
+            regs.psr.set(Psr::Z, zero);
+            let cycles = step(&mut regs, &mut mem);
+            (cycles, regs.pc)
+        };
+        assert_eq!(cycles(&[0xf0, 0x10], false), (2, 0xc002));
+        assert_eq!(cycles(&[0xf0, 0x10], true), (3, 0xc012));
+        assert_eq!(cycles(&[0xf0, 0xfa], true), (4, 0xbffc));
+    }
+
+    #[test]
+    fn unofficial_alu() {
+        // ANC copies N into C, ALR is AND then LSR, AXS subtracts from A & X
+        assert_eq!(alu(0x0b, 0xf0, 0x80, false), (0x80, Psr::C | Psr::N));
+        assert_eq!(alu(0x4b, 0xff, 0x03, false), (0x01, Psr::C));
+        // ARR takes C from bit 6 and V from bits 6 xor 5
+        assert_eq!(alu(0x6b, 0xff, 0xff, true), (0xff, Psr::C | Psr::N));
+        assert_eq!(alu(0x6b, 0xff, 0x80, false), (0x40, Psr::C | Psr::V));
+        // $EB is SBC #imm
+        assert_eq!(alu(0xeb, 0x50, 0x30, true), (0x20, Psr::C));
+
+        let (mut regs, mut mem) = setup(&[0xcb, 0x05]);
+        regs.a = 0x0f;
+        regs.x = 0xfc;
+        step(&mut regs, &mut mem);
+        assert_eq!(regs.x, 0x07);
+        assert!(regs.psr.contains(Psr::C));
+    }
+
+    #[test]
+    fn lax_sax() {
+        // LAX $10, SAX $11
+        let (mut regs, mut mem) = setup(&[0xa7, 0x10, 0x87, 0x11]);
+        mem.write8(0x10, 0x9c);
+        assert_eq!(step(&mut regs, &mut mem), 3);
+        assert_eq!((regs.a, regs.x), (0x9c, 0x9c));
+        assert!(regs.psr.contains(Psr::N));
+        regs.x = 0x0f;
+        step(&mut regs, &mut mem);
+        assert_eq!(mem.peek8(0x11), 0x0c);
+    }
+
+    #[test]
+    fn read_modify_write_combos() {
+        // Memory and A after running `op $10` on $10 = mem and A = a
+        let combo = |op: u8, a: u8, val: u8| {
+            let (mut regs, mut mem) = setup(&[op, 0x10]);
+            mem.write8(0x10, val);
+            regs.a = a;
+            regs.psr.insert(Psr::C);
+            assert_eq!(step(&mut regs, &mut mem), 5);
+            (mem.peek8(0x10), regs.a, regs.psr.contains(Psr::C))
+        };
+        // SLO: ASL then ORA
+        assert_eq!(combo(0x07, 0x01, 0x81), (0x02, 0x03, true));
+        // RLA: ROL then AND
+        assert_eq!(combo(0x27, 0x0f, 0x41), (0x83, 0x03, false));
+        // SRE: LSR then EOR
+        assert_eq!(combo(0x47, 0xff, 0x03), (0x01, 0xfe, true));
+        // RRA: ROR then ADC with the carry ROR shifted out
+        assert_eq!(combo(0x67, 0x10, 0x03), (0x81, 0x92, false));
+        // DCP: DEC then CMP
+        assert_eq!(combo(0xc7, 0x40, 0x41), (0x40, 0x40, true));
+        // ISC: INC then SBC
+        assert_eq!(combo(0xe7, 0x40, 0x0f), (0x10, 0x30, true));
+    }
+
+    #[test]
+    fn unofficial_nops() {
+        // SKB #imm, IGN abs, IGN abs,X across a page, NOP
+        let (mut regs, mut mem) = setup(&[0x80, 0xff, 0x0c, 0x00, 0x02, 0x1c, 0xff, 0x02, 0x1a]);
+        regs.x = 0x01;
+        assert_eq!(step(&mut regs, &mut mem), 2);
+        assert_eq!(step(&mut regs, &mut mem), 4);
+        assert_eq!(step(&mut regs, &mut mem), 5);
+        assert_eq!(step(&mut regs, &mut mem), 2);
+        assert_eq!(regs.pc, 0xc009);
+        assert_eq!((regs.a, regs.x, regs.y), (0, 1, 0));
+    }
+
+    #[test]
+    fn kil_locks_up() {
+        let (mut regs, mut mem) = setup(&[0x02]);
+        step(&mut regs, &mut mem);
+        step(&mut regs, &mut mem);
+        assert_eq!(regs.pc, 0xc000);
+    }
+
+    #[test]
+    fn high_byte_stores() {
+        // SHX $02F0,Y stores X & ($02 + 1)
+        let (mut regs, mut mem) = setup(&[0x9e, 0xf0, 0x02]);
+        regs.x = 0xff;
+        regs.y = 0x01;
+        step(&mut regs, &mut mem);
+        assert_eq!(mem.peek8(0x02f1), 0x03);
+
+        // Crossing a page, the value replaces the high address byte
+        let (mut regs, mut mem) = setup(&[0x9c, 0xf0, 0x05]);
+        regs.x = 0x20;
+        regs.y = 0x02;
+        step(&mut regs, &mut mem);
+        assert_eq!(mem.peek8(0x0210), 0x02);
+    }
+
+    // Point the PPU address at $2000 and fill the start of the nametable
+    fn fill_nametable(mem: &mut Memory, bytes: &[u8]) {
+        mem.write8(0x2006, 0x20);
+        mem.write8(0x2006, 0x00);
+        for &b in bytes {
+            mem.write8(0x2007, b);
+        }
+        mem.write8(0x2006, 0x20);
+        mem.write8(0x2006, 0x00);
+    }
+
+    // Nametable bytes from $2000, read back through $2007
+    fn read_nametable(mem: &mut Memory, len: usize) -> Vec<u8> {
+        mem.write8(0x2006, 0x20);
+        mem.write8(0x2006, 0x00);
+        mem.read8(0x2007);
+        (0..len).map(|_| mem.read8(0x2007)).collect()
+    }
+
+    #[test]
+    fn dummy_read_on_page_cross() {
+        // LDA $20FF,X reads $2007 before fixing the high byte to $21
+        let (mut regs, mut mem) = setup(&[0xbd, 0xff, 0x20]);
+        fill_nametable(&mut mem, &[0x01, 0x02, 0x03]);
+        regs.x = 0x08;
+        step(&mut regs, &mut mem);
+        assert_eq!(regs.a, 0x01);
+    }
+
+    #[test]
+    fn dummy_read_on_indexed_store() {
+        // STA $2007,X reads $2007 even without a page cross
+        let (mut regs, mut mem) = setup(&[0x9d, 0x07, 0x20]);
+        fill_nametable(&mut mem, &[0; 3]);
+        regs.a = 0x55;
+        step(&mut regs, &mut mem);
+        assert_eq!(read_nametable(&mut mem, 3), [0x00, 0x55, 0x00]);
+    }
+
+    #[test]
+    fn dummy_write_on_read_modify_write() {
+        // INC $2007 writes the value it read, then the incremented one
+        let (mut regs, mut mem) = setup(&[0xee, 0x07, 0x20]);
+        fill_nametable(&mut mem, &[0x10, 0, 0, 0]);
+        mem.read8(0x2007);
+        assert_eq!(step(&mut regs, &mut mem), 6);
+        assert_eq!(read_nametable(&mut mem, 4), [0x10, 0x00, 0x10, 0x11]);
+    }
+
+    #[test]
+    fn ppu_runs_three_dots_per_access() {
+        // NOP, LDA $10, STA $0200,X
+        let (mut regs, mut mem) = setup(&[0xea, 0xa5, 0x10, 0x9d, 0x00, 0x02]);
+        for expected in [2, 3, 5] {
+            let (_, dot) = mem.ppu_position();
+            assert_eq!(step(&mut regs, &mut mem), expected);
+            assert_eq!(mem.ppu_position().1, dot + 3 * expected as u16);
+        }
+    }
+}
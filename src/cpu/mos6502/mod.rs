@@ -1,9 +1,16 @@
+pub mod disasm;
 mod isa;
+mod trace;
 
-pub use isa::exec;
+pub use isa::{exec, irq, nmi, reset};
+pub use trace::trace;
 
 use bitflags::bitflags;
 
+pub const NMI_VECTOR: u16 = 0xfffa;
+pub const RESET_VECTOR: u16 = 0xfffc;
+pub const IRQ_VECTOR: u16 = 0xfffe;
+
 #[derive(Debug)]
 pub struct Registers {
     /// Accumulator
@@ -42,8 +49,21 @@ bitflags! {
 }
 
 impl Psr {
+    // B only exists in the copy pushed to the stack
     fn new() -> Self {
-        Self::from_bits_truncate(0x34)
+        Self::from_bits_truncate(0x24)
+    }
+
+    // Set the zero and negative flags from a result
+    fn set_zn(&mut self, val: u8) {
+        self.set(Self::Z, val == 0);
+        self.set(Self::N, val & 0x80 != 0);
+    }
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -54,12 +74,20 @@ impl Registers {
             x: 0,
             y: 0,
             pc: 0,
-            sp: 0x01fd,
+            sp: 0x0100,
             psr: Psr::new(),
         }
     }
 
-    fn bump(&mut self) -> u16 {
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn irq_disabled(&self) -> bool {
+        self.psr.contains(Psr::I)
+    }
+
+    pub fn bump(&mut self) -> u16 {
         let pc = self.pc;
         self.pc = self.pc.wrapping_add(1);
         pc
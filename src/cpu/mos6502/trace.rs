@@ -0,0 +1,72 @@
+use super::disasm;
+use super::isa::{AddressingMode::*, Instruction, Opcode::*};
+use super::Registers;
+use crate::mem::Memory;
+
+// Format the instruction at PC the way nestest.log does, e.g.
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+pub fn trace(regs: &Registers, mem: &Memory, cycles: u64) -> String {
+    let line = disasm::disassemble(regs.pc, |addr| mem.peek8(addr));
+    let Instruction(mode, opcode) = Instruction::from(line.bytes[0]);
+
+    let lo = line.bytes.get(1).copied().unwrap_or(0);
+    let abs = u16::from_le_bytes([lo, line.bytes.get(2).copied().unwrap_or(0)]);
+    let peek_zp16 = |ptr: u8| {
+        u16::from_le_bytes([mem.peek8(ptr as u16), mem.peek8(ptr.wrapping_add(1) as u16)])
+    };
+
+    // Resolve the effective address and the value currently stored there
+    let resolved = match mode {
+        ZeroPage => format!(" = {:02X}", mem.peek8(lo as u16)),
+        ZeroPageX | ZeroPageY => {
+            let index = if mode == ZeroPageX { regs.x } else { regs.y };
+            let addr = lo.wrapping_add(index);
+            format!(" @ {addr:02X} = {:02X}", mem.peek8(addr as u16))
+        }
+        Absolute if matches!(opcode, JMP | JSR) => String::new(),
+        Absolute => format!(" = {:02X}", mem.peek8(abs)),
+        AbsoluteX | AbsoluteY => {
+            let index = if mode == AbsoluteX { regs.x } else { regs.y };
+            let addr = abs.wrapping_add(index as u16);
+            format!(" @ {addr:04X} = {:02X}", mem.peek8(addr))
+        }
+        Indirect => {
+            let hi = (abs & 0xff00) | (abs.wrapping_add(1) & 0x00ff);
+            let target = u16::from_le_bytes([mem.peek8(abs), mem.peek8(hi)]);
+            format!(" = {target:04X}")
+        }
+        IndirectX => {
+            let ptr = lo.wrapping_add(regs.x);
+            let addr = peek_zp16(ptr);
+            format!(" @ {ptr:02X} = {addr:04X} = {:02X}", mem.peek8(addr))
+        }
+        IndirectY => {
+            let base = peek_zp16(lo);
+            let addr = base.wrapping_add(regs.y as u16);
+            format!(" = {base:04X} @ {addr:04X} = {:02X}", mem.peek8(addr))
+        }
+        _ => String::new(),
+    };
+
+    // nestest's names for the unofficial opcodes we call something else
+    let mnemonic = match opcode {
+        SKB | IGN => "NOP",
+        ISC => "ISB",
+        _ => &line.mnemonic,
+    };
+    let raw: Vec<String> = line.bytes.iter().map(|b| format!("{b:02X}")).collect();
+    let star = if line.unofficial { '*' } else { ' ' };
+    let (scanline, dot) = mem.ppu_position();
+
+    format!(
+        "{:04X}  {:<8} {star}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{scanline:>3},{dot:>3} CYC:{cycles}",
+        line.addr,
+        raw.join(" "),
+        format!("{mnemonic} {}{resolved}", line.operand).trim_end(),
+        regs.a,
+        regs.x,
+        regs.y,
+        regs.psr.bits(),
+        regs.sp as u8,
+    )
+}
@@ -0,0 +1,82 @@
+use std::error::Error;
+use std::io::BufRead;
+
+use super::Cpu;
+use crate::cartridge::Cartridge;
+use crate::mapper;
+use crate::mem::Memory;
+
+// nestest's automated mode starts here instead of at the reset vector
+const AUTOMATION_START: u16 = 0xc000;
+
+/// First line where a run disagrees with the reference log
+#[derive(Debug)]
+pub struct Divergence {
+    /// 1-based line number in the reference log
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+// Run nestest.nes in automated mode, comparing the trace against a reference
+// log line by line. Returns the first divergence, or None if the whole log
+// matched
+pub fn run(rom: &[u8], reference: impl BufRead) -> Result<Option<Divergence>, Box<dyn Error>> {
+    let mut mem = Memory::new(mapper::new(Cartridge::new(rom)?)?);
+    let mut cpu = Cpu::new();
+    cpu.reset(&mut mem);
+    cpu.set_pc(AUTOMATION_START);
+
+    for (i, expected) in reference.lines().enumerate() {
+        let expected = expected?;
+        let actual = cpu.trace(&mem);
+        if actual.trim_end() != expected.trim_end() {
+            return Ok(Some(Divergence {
+                line: i + 1,
+                expected,
+                actual,
+            }));
+        }
+        cpu.step(&mut mem);
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_image;
+
+    // LDX #$05, STX $10, INX, JMP $C000
+    const PROGRAM: [u8; 8] = [0xa2, 0x05, 0x86, 0x10, 0xe8, 0x4c, 0x00, 0xc0];
+
+    #[rustfmt::skip]
+    const LOG: [&str; 5] = [
+        "C000  A2 05     LDX #$05                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+        "C002  86 10     STX $10 = 00                    A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9",
+        "C004  E8        INX                             A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 36 CYC:12",
+        "C005  4C 00 C0  JMP $C000                       A:00 X:06 Y:00 P:24 SP:FD PPU:  0, 42 CYC:14",
+        "C000  A2 05     LDX #$05                        A:00 X:06 Y:00 P:24 SP:FD PPU:  0, 51 CYC:17",
+    ];
+
+    #[test]
+    fn matching_log() {
+        let log = LOG.join("\n");
+        let div = run(&test_image(&PROGRAM), log.as_bytes()).unwrap();
+        assert!(div.is_none(), "{div:?}");
+    }
+
+    #[test]
+    fn first_divergence() {
+        let mut log = LOG.map(String::from);
+        log[2] = log[2].replace("X:05", "X:07");
+        log[4] = log[4].replace("CYC:17", "CYC:18");
+        let div = run(&test_image(&PROGRAM), log.join("\n").as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(div.line, 3);
+        assert_eq!(div.expected, log[2]);
+        assert_eq!(div.actual, LOG[2]);
+    }
+}
@@ -1,3 +1,8 @@
+pub mod apu;
+pub mod cartridge;
+pub mod console;
+pub mod controller;
 pub mod cpu;
+pub mod mapper;
 pub mod mem;
 pub mod ppu;
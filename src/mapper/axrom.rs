@@ -0,0 +1,66 @@
+use super::{bus_conflicts, Chr, Mapper};
+use crate::cartridge::Cartridge;
+use crate::ppu::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+/// Mapper 7, ANROM/AMROM/AOROM. Switchable 32 KB PRG and a register
+/// picking which CIRAM page all four nametables show
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    bus_conflicts: bool,
+    /// PRG bank in bits 0-2, nametable page in bit 4
+    reg: u8,
+}
+
+impl Axrom {
+    pub fn new(cart: Cartridge) -> Self {
+        Self {
+            // Only AMROM and AOROM have conflicts, and games on those
+            // avoid them
+            bus_conflicts: bus_conflicts(&cart.header, false),
+            chr: Chr::new(&cart.header, cart.chr_rom),
+            prg_rom: cart.prg_rom,
+            reg: 0,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xffff => {
+                let offset = (self.reg & 0x07) as usize * PRG_BANK_SIZE + (addr & 0x7fff) as usize;
+                Some(self.prg_rom[offset % self.prg_rom.len()])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        if addr >= 0x8000 {
+            self.reg = if self.bus_conflicts {
+                val & self.cpu_peek(addr).unwrap_or(0xff)
+            } else {
+                val
+            };
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        self.chr.write(addr as usize, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.reg & 0x10 != 0 {
+            Mirroring::SingleScreenUpper
+        } else {
+            Mirroring::SingleScreenLower
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use super::{Chr, Mapper};
+use crate::cartridge::Cartridge;
+use crate::ppu::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x1000;
+const PRG_RAM_SIZE: usize = 0x2000;
+
+/// Mapper 34, two unrelated boards sharing a number. BNROM switches 32 KB
+/// of PRG at $8000 over 8 KB of CHR RAM. NINA-001 has PRG RAM with the
+/// registers at $7FFD-$7FFF and two 4 KB CHR ROM banks
+pub struct Bnrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    nina: bool,
+    prg_bank: u8,
+    chr_banks: [u8; 2],
+}
+
+impl Bnrom {
+    pub fn new(cart: Cartridge) -> Self {
+        // Submapper 1 is NINA-001, 2 is BNROM. Without one, only
+        // NINA-001 has more than 8 KB of CHR
+        let nina = match cart.header.submapper {
+            1 => true,
+            2 => false,
+            _ => cart.chr_rom.len() > 0x2000,
+        };
+        Self {
+            nina,
+            chr: Chr::new(&cart.header, cart.chr_rom),
+            mirroring: cart.header.mirroring,
+            prg_rom: cart.prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        if self.nina {
+            let bank = self.chr_banks[(addr >> 12) as usize];
+            bank as usize * CHR_BANK_SIZE + (addr & 0x0fff) as usize
+        } else {
+            addr as usize
+        }
+    }
+}
+
+impl Mapper for Bnrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff if self.nina => Some(self.prg_ram[(addr & 0x1fff) as usize]),
+            0x8000..=0xffff => {
+                let offset = self.prg_bank as usize * PRG_BANK_SIZE + (addr & 0x7fff) as usize;
+                Some(self.prg_rom[offset % self.prg_rom.len()])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // The registers are written through to RAM as well
+            0x6000..=0x7fff if self.nina => {
+                self.prg_ram[(addr & 0x1fff) as usize] = val;
+                match addr {
+                    0x7ffd => self.prg_bank = val & 0x01,
+                    0x7ffe => self.chr_banks[0] = val & 0x0f,
+                    0x7fff => self.chr_banks[1] = val & 0x0f,
+                    _ => {}
+                }
+            }
+            0x8000..=0xffff if !self.nina => {
+                // BNROM has bus conflicts
+                self.prg_bank = val & self.cpu_peek(addr).unwrap_or(0xff);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
@@ -0,0 +1,82 @@
+use crate::cartridge::RomHeader;
+
+/// CHR RAM size when the header doesn't give one
+const DEFAULT_CHR_RAM_SIZE: usize = 0x2000;
+
+/// Pattern table memory on the cartridge, either CHR ROM or CHR RAM
+pub struct Chr {
+    data: Vec<u8>,
+    /// Writable, the board has CHR RAM instead of ROM
+    ram: bool,
+}
+
+impl Chr {
+    // Boards without CHR ROM have CHR RAM, NES 2.0 headers give its size
+    pub fn new(header: &RomHeader, chr_rom: Vec<u8>) -> Self {
+        if !chr_rom.is_empty() {
+            return Self {
+                data: chr_rom,
+                ram: false,
+            };
+        }
+
+        let size = match header.chr_ram_size + header.chr_nvram_size {
+            0 => DEFAULT_CHR_RAM_SIZE,
+            size => size,
+        };
+        Self {
+            data: vec![0; size],
+            ram: true,
+        }
+    }
+
+    // Byte at an offset into the whole CHR, wrapping at its size
+    pub fn read(&self, offset: usize) -> u8 {
+        self.data[offset % self.data.len()]
+    }
+
+    // Writes only stick on CHR RAM
+    pub fn write(&mut self, offset: usize, val: u8) {
+        if self.ram {
+            let len = self.data.len();
+            self.data[offset % len] = val;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Header with no CHR ROM, NES 2.0 when given a CHR RAM size byte
+    fn header(chr_ram: Option<u8>) -> RomHeader {
+        let mut rom = *b"NES\x1a\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+        if let Some(size) = chr_ram {
+            rom[7] = 0x08;
+            rom[11] = size;
+        }
+        RomHeader::parse(&rom).unwrap()
+    }
+
+    #[test]
+    fn rom_is_read_only() {
+        let mut chr = Chr::new(&header(None), vec![0x11; 0x2000]);
+        chr.write(0x0123, 0x22);
+        assert_eq!(chr.read(0x0123), 0x11);
+    }
+
+    #[test]
+    fn ram() {
+        // 8 KB when the header doesn't say
+        let mut chr = Chr::new(&header(None), Vec::new());
+        chr.write(0x1fff, 0x22);
+        assert_eq!(chr.read(0x1fff), 0x22);
+        assert_eq!(chr.read(0x3fff), 0x22);
+
+        // NES 2.0 gives 64 << n bytes
+        let mut chr = Chr::new(&header(Some(0x09)), Vec::new());
+        chr.write(0x7fff, 0x33);
+        assert_eq!(chr.read(0x7fff), 0x33);
+        assert_eq!(chr.read(0x1fff), 0x00);
+    }
+}
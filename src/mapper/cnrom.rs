@@ -0,0 +1,59 @@
+use super::{bus_conflicts, Chr, Mapper};
+use crate::cartridge::Cartridge;
+use crate::ppu::Mirroring;
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Mapper 3, CNROM. Fixed PRG like NROM, switchable 8 KB CHR
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    bank: u8,
+}
+
+impl Cnrom {
+    pub fn new(cart: Cartridge) -> Self {
+        Self {
+            bus_conflicts: bus_conflicts(&cart.header, true),
+            chr: Chr::new(&cart.header, cart.chr_rom),
+            mirroring: cart.header.mirroring,
+            prg_rom: cart.prg_rom,
+            bank: 0,
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_rom[(addr & 0x7fff) as usize % self.prg_rom.len()]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        if addr >= 0x8000 {
+            self.bank = if self.bus_conflicts {
+                val & self.cpu_peek(addr).unwrap_or(0xff)
+            } else {
+                val
+            };
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr
+            .read(self.bank as usize * CHR_BANK_SIZE + addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        self.chr
+            .write(self.bank as usize * CHR_BANK_SIZE + addr as usize, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
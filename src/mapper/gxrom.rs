@@ -0,0 +1,75 @@
+use super::{Chr, Mapper};
+use crate::cartridge::Cartridge;
+use crate::ppu::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Mapper 66 (GxROM) and 11 (Color Dreams). One register with a 32 KB PRG
+/// bank and an 8 KB CHR bank, the boards put them in opposite nibbles
+pub struct Gxrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    /// Color Dreams layout, PRG in the low bits and CHR in the high ones
+    color_dreams: bool,
+    prg_bank: u8,
+    chr_bank: u8,
+}
+
+impl Gxrom {
+    pub fn new(cart: Cartridge) -> Self {
+        Self {
+            color_dreams: cart.header.mapper == 11,
+            chr: Chr::new(&cart.header, cart.chr_rom),
+            mirroring: cart.header.mirroring,
+            prg_rom: cart.prg_rom,
+            prg_bank: 0,
+            chr_bank: 0,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.chr_bank as usize * CHR_BANK_SIZE + addr as usize
+    }
+}
+
+impl Mapper for Gxrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xffff => {
+                let offset = self.prg_bank as usize * PRG_BANK_SIZE + (addr & 0x7fff) as usize;
+                Some(self.prg_rom[offset % self.prg_rom.len()])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        // Both boards have bus conflicts
+        let val = val & self.cpu_peek(addr).unwrap_or(0xff);
+        if self.color_dreams {
+            self.prg_bank = val & 0x03;
+            self.chr_bank = val >> 4;
+        } else {
+            self.prg_bank = (val >> 4) & 0x03;
+            self.chr_bank = val & 0x03;
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
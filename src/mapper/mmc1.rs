@@ -0,0 +1,379 @@
+use super::{Chr, Mapper};
+use crate::cartridge::Cartridge;
+use crate::ppu::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+const PRG_RAM_BANK_SIZE: usize = 0x2000;
+/// PRG ROM above this is split into 256 KB halves, SUROM and SXROM
+const PRG_OUTER_SIZE: usize = 0x40000;
+/// Shift register value with only the end marker in it
+const SHIFT_RESET: u8 = 0x10;
+
+/// Mapper 1, Nintendo MMC1. Registers are loaded one bit per write
+/// through a 5 bit shift register
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    /// MMC1A, mapper 155, has no PRG RAM disable bit
+    mmc1a: bool,
+    /// SNROM, where CHR register bit 4 disables the PRG RAM
+    snrom: bool,
+    /// Bits shifted in so far, the marker bit reaching bit 0 means full
+    shift: u8,
+    /// Mirroring, PRG mode and CHR mode
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+    /// A12 of the last pattern fetch. In 4 KB CHR mode it picks the CHR
+    /// register the board's extra PRG lines come from
+    chr_a12: bool,
+    /// CPU cycles since power-on
+    cycle: u64,
+    /// Cycle of the last write, writes on the following cycle are ignored
+    last_write: u64,
+}
+
+impl Mmc1 {
+    pub fn new(cart: Cartridge) -> Self {
+        let header = &cart.header;
+        let ram_size = (header.prg_ram_size + header.prg_nvram_size).max(PRG_RAM_BANK_SIZE);
+        // SNROM is 8 KB CHR RAM and 8 KB PRG RAM. iNES doesn't give the CHR
+        // RAM size and always claims PRG RAM, so there the battery tells it
+        // from SGROM
+        let snrom = header.chr_rom_size == 0
+            && header.prg_rom_size <= PRG_OUTER_SIZE
+            && header.prg_ram_size + header.prg_nvram_size == PRG_RAM_BANK_SIZE
+            && if header.nes2 {
+                header.chr_ram_size + header.chr_nvram_size == 0x2000
+            } else {
+                header.battery
+            };
+        let mut prg_ram = vec![0; ram_size];
+        if let Some(trainer) = &cart.trainer {
+            prg_ram[0x1000..0x1000 + trainer.len()].copy_from_slice(trainer);
+        }
+
+        Self {
+            mmc1a: header.mapper == 155,
+            snrom,
+            chr: Chr::new(header, cart.chr_rom),
+            prg_rom: cart.prg_rom,
+            prg_ram,
+            shift: SHIFT_RESET,
+            control: 0x0c,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+            chr_a12: false,
+            cycle: 0,
+            last_write: u64::MAX,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x8000..=0x9fff => self.control = val,
+            0xa000..=0xbfff => self.chr_bank0 = val,
+            0xc000..=0xdfff => self.chr_bank1 = val,
+            _ => self.prg_bank = val,
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = (self.prg_bank & 0x0f) as usize;
+        let last = 0x0f;
+        let high = addr >= 0xc000;
+        let bank = match (self.control >> 2) & 0x03 {
+            // 32 KB, the low bit is ignored
+            0 | 1 => (bank & !1) | usize::from(high),
+            // First bank fixed at $8000
+            2 => {
+                if high {
+                    bank
+                } else {
+                    0
+                }
+            }
+            // Last bank fixed at $C000
+            _ => {
+                if high {
+                    last
+                } else {
+                    bank
+                }
+            }
+        };
+        let offset = (self.prg_outer() + bank) * PRG_BANK_SIZE + (addr & 0x3fff) as usize;
+        offset % self.prg_rom.len()
+    }
+
+    // CHR register driving the CHR lines right now, boards that repurpose
+    // them see this one. In 8 KB mode it's always the first
+    fn active_chr_bank(&self) -> u8 {
+        if self.control & 0x10 != 0 && self.chr_a12 {
+            self.chr_bank1
+        } else {
+            self.chr_bank0
+        }
+    }
+
+    // SUROM and SXROM select a 256 KB PRG half with bit 4 of the CHR
+    // register, the CHR is only 8 KB so the bit is free
+    fn prg_outer(&self) -> usize {
+        if self.prg_rom.len() > PRG_OUTER_SIZE {
+            usize::from(self.active_chr_bank() & 0x10)
+        } else {
+            0
+        }
+    }
+
+    // SOROM and SXROM bank PRG RAM with CHR register bits 3 or 2-3
+    fn prg_ram_offset(&self, addr: u16) -> usize {
+        let chr_bank = self.active_chr_bank();
+        let bank = match self.prg_ram.len() / PRG_RAM_BANK_SIZE {
+            2 => (chr_bank >> 3) & 0x01,
+            4 => (chr_bank >> 2) & 0x03,
+            _ => 0,
+        };
+        bank as usize * PRG_RAM_BANK_SIZE + (addr & 0x1fff) as usize
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        (self.mmc1a || self.prg_bank & 0x10 == 0)
+            && !(self.snrom && self.active_chr_bank() & 0x10 != 0)
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = if self.control & 0x10 != 0 {
+            // Two 4 KB banks
+            if addr < 0x1000 {
+                self.chr_bank0
+            } else {
+                self.chr_bank1
+            }
+        } else {
+            // One 8 KB bank, the low bit is ignored
+            (self.chr_bank0 & !1) | (addr >> 12) as u8
+        };
+        bank as usize * CHR_BANK_SIZE + (addr & 0x0fff) as usize
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                Some(self.prg_ram[self.prg_ram_offset(addr)])
+            }
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_offset(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                let offset = self.prg_ram_offset(addr);
+                self.prg_ram[offset] = val;
+            }
+            0x8000..=0xffff => {
+                // Bit 7 resets the shift register and sets PRG mode 3
+                if val & 0x80 != 0 {
+                    self.shift = SHIFT_RESET;
+                    self.control |= 0x0c;
+                } else if self.cycle != self.last_write.wrapping_add(1) {
+                    // The second write of a read-modify-write instruction
+                    // lands on the next cycle and doesn't register
+                    let full = self.shift & 0x01 != 0;
+                    self.shift = (self.shift >> 1) | ((val & 0x01) << 4);
+                    if full {
+                        self.write_register(addr, self.shift);
+                        self.shift = SHIFT_RESET;
+                    }
+                }
+                self.last_write = self.cycle;
+            }
+            _ => {}
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, val);
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        if addr < 0x2000 {
+            self.chr_a12 = addr & 0x1000 != 0;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MMC1 with the given header bytes 4-11, every PRG byte holds its 16 KB
+    // bank number and every CHR byte its 4 KB bank number
+    fn mmc1(header: [u8; 8]) -> Mmc1 {
+        let prg = header[0] as usize * PRG_BANK_SIZE;
+        let chr = header[1] as usize * 2 * CHR_BANK_SIZE;
+        let mut rom = vec![0; 16 + prg + chr];
+        rom[..4].copy_from_slice(b"NES\x1a");
+        rom[4..12].copy_from_slice(&header);
+        for (i, b) in rom[16..].iter_mut().enumerate() {
+            *b = if i < prg {
+                (i / PRG_BANK_SIZE) as u8
+            } else {
+                ((i - prg) / CHR_BANK_SIZE) as u8
+            };
+        }
+        Mmc1::new(Cartridge::new(&rom).unwrap())
+    }
+
+    // Five serial writes, a cycle apart so none is dropped
+    fn load(mapper: &mut Mmc1, addr: u16, val: u8) {
+        for i in 0..5 {
+            mapper.cpu_clock();
+            mapper.cpu_clock();
+            mapper.cpu_write(addr, (val >> i) & 0x01);
+        }
+    }
+
+    #[test]
+    fn prg_modes() {
+        let mut m = mmc1([8, 1, 0x10, 0, 0, 0, 0, 0]);
+        // Power on is mode 3, last bank fixed at $C000
+        load(&mut m, 0xe000, 2);
+        assert_eq!(m.cpu_peek(0x8000), Some(2));
+        assert_eq!(m.cpu_peek(0xc000), Some(7));
+
+        load(&mut m, 0x8000, 0x08);
+        assert_eq!(m.cpu_peek(0x8000), Some(0));
+        assert_eq!(m.cpu_peek(0xc000), Some(2));
+
+        load(&mut m, 0x8000, 0x00);
+        load(&mut m, 0xe000, 5);
+        assert_eq!(m.cpu_peek(0x8000), Some(4));
+        assert_eq!(m.cpu_peek(0xc000), Some(5));
+    }
+
+    #[test]
+    fn chr_modes() {
+        let mut m = mmc1([2, 4, 0x10, 0, 0, 0, 0, 0]);
+        load(&mut m, 0xa000, 3);
+        load(&mut m, 0xc000, 6);
+        assert_eq!(m.ppu_read(0x0000), 2);
+        assert_eq!(m.ppu_read(0x1000), 3);
+
+        load(&mut m, 0x8000, 0x1c);
+        assert_eq!(m.ppu_read(0x0000), 3);
+        assert_eq!(m.ppu_read(0x1000), 6);
+    }
+
+    #[test]
+    fn reset_and_consecutive_writes() {
+        let mut m = mmc1([8, 1, 0x10, 0, 0, 0, 0, 0]);
+        load(&mut m, 0xe000, 3);
+        // A write on the very next cycle is ignored, like the second write
+        // of INC $FFFF
+        m.cpu_write(0xe000, 1);
+        m.cpu_clock();
+        m.cpu_write(0xe000, 1);
+        for _ in 0..4 {
+            m.cpu_clock();
+            m.cpu_clock();
+            m.cpu_write(0xe000, 0);
+        }
+        assert_eq!(m.cpu_peek(0x8000), Some(1));
+
+        // Bit 7 drops the bits shifted in so far and fixes the last bank
+        load(&mut m, 0x8000, 0x08);
+        m.cpu_clock();
+        m.cpu_clock();
+        m.cpu_write(0x8000, 1);
+        m.cpu_clock();
+        m.cpu_clock();
+        m.cpu_write(0x8000, 0x80);
+        assert_eq!(m.cpu_peek(0xc000), Some(7));
+        load(&mut m, 0xe000, 4);
+        assert_eq!(m.cpu_peek(0x8000), Some(4));
+    }
+
+    #[test]
+    fn surom_outer_bank_follows_last_chr_register() {
+        let mut m = mmc1([32, 0, 0x10, 0, 0, 0, 0, 0]);
+        load(&mut m, 0x8000, 0x1c);
+        load(&mut m, 0xa000, 0x00);
+        load(&mut m, 0xc000, 0x10);
+        m.ppu_address(0x0000);
+        assert_eq!(m.cpu_peek(0xc000), Some(15));
+        m.ppu_address(0x1000);
+        assert_eq!(m.cpu_peek(0xc000), Some(31));
+        // Nametable fetches don't change it
+        m.ppu_address(0x2000);
+        assert_eq!(m.cpu_peek(0xc000), Some(31));
+
+        // In 8 KB mode only the first register counts
+        load(&mut m, 0x8000, 0x0c);
+        assert_eq!(m.cpu_peek(0xc000), Some(15));
+    }
+
+    #[test]
+    fn sorom_ram_banks() {
+        let mut m = mmc1([16, 0, 0x12, 0, 2, 0, 0, 0]);
+        load(&mut m, 0xa000, 0x00);
+        m.cpu_write(0x6000, 1);
+        load(&mut m, 0xa000, 0x08);
+        assert_eq!(m.cpu_peek(0x6000), Some(0));
+        m.cpu_write(0x6000, 2);
+        load(&mut m, 0xa000, 0x00);
+        assert_eq!(m.cpu_peek(0x6000), Some(1));
+    }
+
+    #[test]
+    fn snrom_ram_disable() {
+        // Battery backed 8 KB RAM with CHR RAM, SNROM
+        let mut m = mmc1([8, 0, 0x12, 0, 0, 0, 0, 0]);
+        m.cpu_write(0x6000, 0x55);
+        load(&mut m, 0xa000, 0x10);
+        assert_eq!(m.cpu_peek(0x6000), None);
+        load(&mut m, 0xa000, 0x00);
+        assert_eq!(m.cpu_peek(0x6000), Some(0x55));
+        // The PRG register bit disables it on every board
+        load(&mut m, 0xe000, 0x10);
+        assert_eq!(m.cpu_peek(0x6000), None);
+
+        // NES 2.0 SGROM-like board with 32 KB CHR RAM isn't SNROM
+        let mut m = mmc1([8, 0, 0x10, 0x08, 0, 0, 0x70, 0x09]);
+        m.cpu_write(0x6000, 0x55);
+        load(&mut m, 0xa000, 0x10);
+        assert_eq!(m.cpu_peek(0x6000), Some(0x55));
+
+        // Neither is an iNES board without a battery
+        let mut m = mmc1([8, 0, 0x10, 0, 0, 0, 0, 0]);
+        m.cpu_write(0x6000, 0x55);
+        load(&mut m, 0xa000, 0x10);
+        assert_eq!(m.cpu_peek(0x6000), Some(0x55));
+    }
+}
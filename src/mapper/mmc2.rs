@@ -0,0 +1,207 @@
+use super::{Chr, Mapper};
+use crate::cartridge::Cartridge;
+use crate::ppu::Mirroring;
+
+const CHR_BANK_SIZE: usize = 0x1000;
+const PRG_RAM_SIZE: usize = 0x2000;
+
+/// Mappers 9 and 10, Nintendo MMC2 and MMC4. Each 4 KB CHR half has two
+/// banks, and a latch picks between them when the PPU fetches tile $FD or
+/// $FE from that half
+pub struct Mmc2 {
+    prg_rom: Vec<u8>,
+    /// 8 KB at $6000, MMC4 only
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    /// MMC4 switches 16 KB of PRG instead of 8 KB, and its left latch
+    /// triggers on a whole tile row like the right one
+    mmc4: bool,
+    prg_bank: u8,
+    /// FD and FE banks for each half
+    chr_banks: [[u8; 2]; 2],
+    /// Selected bank per half, 0 for FD and 1 for FE
+    latches: [usize; 2],
+    horizontal: bool,
+}
+
+impl Mmc2 {
+    pub fn new(cart: Cartridge) -> Self {
+        let mmc4 = cart.header.mapper == 10;
+        Self {
+            mmc4,
+            chr: Chr::new(&cart.header, cart.chr_rom),
+            prg_rom: cart.prg_rom,
+            prg_ram: if mmc4 { vec![0; PRG_RAM_SIZE] } else { vec![] },
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [1, 1],
+            horizontal: false,
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let (size, mask) = if self.mmc4 {
+            (0x4000, 0x3fff)
+        } else {
+            (0x2000, 0x1fff)
+        };
+        let banks = self.prg_rom.len() / size;
+        // Only the first slot switches, the rest is fixed to the end of PRG
+        let slot = (addr as usize - 0x8000) / size;
+        let bank = if slot == 0 {
+            self.prg_bank as usize
+        } else {
+            (banks + slot).saturating_sub(0x8000 / size)
+        };
+        (bank * size + (addr as usize & mask)) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let half = (addr >> 12) as usize;
+        let bank = self.chr_banks[half][self.latches[half]];
+        bank as usize * CHR_BANK_SIZE + (addr & 0x0fff) as usize
+    }
+
+    // Flip a latch after the fetch of the last byte of tile $FD or $FE. On
+    // MMC2 the left half only reacts to $0FD8 and $0FE8 exactly
+    fn update_latch(&mut self, addr: u16) {
+        let half = (addr >> 12) as usize;
+        let exact = half == 0 && !self.mmc4;
+        let tile = addr & 0x0ff8;
+        let latch = match (tile, addr & 0x0007) {
+            (0x0fd8, 0) => 0,
+            (0x0fe8, 0) => 1,
+            (0x0fd8, _) if !exact => 0,
+            (0x0fe8, _) if !exact => 1,
+            _ => return,
+        };
+        self.latches[half] = latch;
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff if self.mmc4 => Some(self.prg_ram[(addr & 0x1fff) as usize]),
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_offset(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x6000..=0x7fff if self.mmc4 => self.prg_ram[(addr & 0x1fff) as usize] = val,
+            0xa000..=0xafff => self.prg_bank = val & 0x0f,
+            0xb000..=0xbfff => self.chr_banks[0][0] = val & 0x1f,
+            0xc000..=0xcfff => self.chr_banks[0][1] = val & 0x1f,
+            0xd000..=0xdfff => self.chr_banks[1][0] = val & 0x1f,
+            0xe000..=0xefff => self.chr_banks[1][1] = val & 0x1f,
+            0xf000..=0xffff => self.horizontal = val & 0x01 != 0,
+            _ => {}
+        }
+    }
+
+    // The byte comes from the bank selected before the fetch, the latch
+    // only switches afterwards
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let val = self.chr.read(self.chr_offset(addr));
+        self.update_latch(addr);
+        val
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.horizontal {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MMC2 or MMC4 with 128 KB PRG and 128 KB CHR. Every PRG byte holds
+    // its 8 KB bank number and every CHR byte its 4 KB bank number
+    fn mmc2(mapper: u8) -> Mmc2 {
+        let (prg, chr) = (0x20000, 0x20000);
+        let mut rom = vec![0; 16 + prg + chr];
+        rom[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1a, 0x08, 0x10, mapper << 4, 0]);
+        for (i, b) in rom[16..16 + prg].iter_mut().enumerate() {
+            *b = (i / 0x2000) as u8;
+        }
+        for (i, b) in rom[16 + prg..].iter_mut().enumerate() {
+            *b = (i / CHR_BANK_SIZE) as u8;
+        }
+        let mut m = Mmc2::new(Cartridge::new(&rom).unwrap());
+        for (addr, bank) in [(0xb000, 1), (0xc000, 2), (0xd000, 3), (0xe000, 4)] {
+            m.cpu_write(addr, bank);
+        }
+        m
+    }
+
+    fn banks(m: &mut Mmc2) -> [u8; 2] {
+        [m.ppu_read(0x0000), m.ppu_read(0x1000)]
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut m = mmc2(9);
+        m.cpu_write(0xa000, 3);
+        let slots = [0x8000, 0xa000, 0xc000, 0xe000];
+        assert_eq!(slots.map(|a| m.cpu_peek(a).unwrap()), [3, 13, 14, 15]);
+        assert_eq!(m.cpu_peek(0x6000), None);
+
+        let mut m = mmc2(10);
+        m.cpu_write(0xa000, 3);
+        assert_eq!(slots.map(|a| m.cpu_peek(a).unwrap()), [6, 7, 14, 15]);
+        m.cpu_write(0x6000, 0x55);
+        assert_eq!(m.cpu_peek(0x6000), Some(0x55));
+    }
+
+    #[test]
+    fn latches() {
+        let mut m = mmc2(9);
+        assert_eq!(banks(&mut m), [2, 4]);
+        // The triggering fetch still comes from the old bank
+        assert_eq!(m.ppu_read(0x0fd8), 2);
+        assert_eq!(banks(&mut m), [1, 4]);
+        m.ppu_read(0x1fd8);
+        assert_eq!(banks(&mut m), [1, 3]);
+        m.ppu_read(0x0fe8);
+        m.ppu_read(0x1fe8);
+        assert_eq!(banks(&mut m), [2, 4]);
+        // Other tiles leave the latches alone
+        m.ppu_read(0x0fc8);
+        m.ppu_read(0x1ff8);
+        assert_eq!(banks(&mut m), [2, 4]);
+    }
+
+    #[test]
+    fn left_latch_range() {
+        // On MMC2 the left latch only reacts to the first byte of the tile
+        let mut m = mmc2(9);
+        m.ppu_read(0x0fdf);
+        m.ppu_read(0x1fdf);
+        assert_eq!(banks(&mut m), [2, 3]);
+
+        let mut m = mmc2(10);
+        m.ppu_read(0x0fdf);
+        m.ppu_read(0x1fdf);
+        assert_eq!(banks(&mut m), [1, 3]);
+    }
+
+    #[test]
+    fn mirroring() {
+        let mut m = mmc2(9);
+        assert_eq!(m.mirroring(), Mirroring::Vertical);
+        m.cpu_write(0xf000, 0x01);
+        assert_eq!(m.mirroring(), Mirroring::Horizontal);
+    }
+}
@@ -0,0 +1,340 @@
+use super::{Chr, Mapper};
+use crate::cartridge::Cartridge;
+use crate::ppu::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const PRG_RAM_SIZE: usize = 0x2000;
+/// CPU cycles A12 has to stay low before a rise clocks the IRQ counter.
+/// Filters out the short drops between sprite fetches
+const A12_LOW_CYCLES: u8 = 3;
+
+/// Mapper 4, Nintendo MMC3. 8 KB PRG and 1/2 KB CHR banks, and a counter
+/// clocked by PPU A12 that fires an IRQ after a set number of scanlines
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    /// Four-screen boards ignore the mirroring register
+    four_screen: bool,
+    /// MMC3A (NEC) only fires when the counter decrements to 0 or is
+    /// reloaded with 0 after $C001, MMC3C (Sharp) fires whenever it's 0
+    mmc3a: bool,
+    /// Register written by $8001, PRG and CHR modes
+    bank_select: u8,
+    /// R0-R7
+    banks: [u8; 8],
+    horizontal: bool,
+    /// PRG RAM chip enable and write protect
+    ram_protect: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq: bool,
+    /// Last A12 seen on the PPU bus and how long it has been low
+    a12: bool,
+    a12_low: u8,
+}
+
+impl Mmc3 {
+    pub fn new(cart: Cartridge) -> Self {
+        let header = &cart.header;
+        Self {
+            four_screen: header.mirroring == Mirroring::FourScreen,
+            mmc3a: header.submapper == 4,
+            chr: Chr::new(header, cart.chr_rom),
+            prg_rom: cart.prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            horizontal: false,
+            ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq: false,
+            a12: false,
+            a12_low: 0,
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+        let slot = ((addr >> 13) & 0x03) as usize;
+        // PRG mode 1 swaps the R6 slot and the fixed second to last bank
+        let slot = if self.bank_select & 0x40 != 0 && slot & 0x01 == 0 {
+            slot ^ 0x02
+        } else {
+            slot
+        };
+        let bank = match slot {
+            0 => (self.banks[6] & 0x3f) as usize,
+            1 => (self.banks[7] & 0x3f) as usize,
+            2 => banks.saturating_sub(2),
+            _ => banks.saturating_sub(1),
+        };
+        (bank * PRG_BANK_SIZE + (addr & 0x1fff) as usize) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        // CHR mode 1 swaps the 2 KB and 1 KB halves
+        let addr = if self.bank_select & 0x80 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        let bank = match addr >> 10 {
+            0 => self.banks[0] & 0xfe,
+            1 => self.banks[0] | 0x01,
+            2 => self.banks[1] & 0xfe,
+            3 => self.banks[1] | 0x01,
+            n => self.banks[n as usize - 2],
+        };
+        bank as usize * CHR_BANK_SIZE + (addr & 0x03ff) as usize
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_protect & 0x80 != 0
+    }
+
+    fn clock_irq(&mut self) {
+        let prev = self.irq_counter;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        let fire = if self.mmc3a {
+            (prev > 0 || self.irq_reload) && self.irq_counter == 0
+        } else {
+            self.irq_counter == 0
+        };
+        if fire && self.irq_enabled {
+            self.irq = true;
+        }
+        self.irq_reload = false;
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled() => Some(self.prg_ram[(addr & 0x1fff) as usize]),
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_offset(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        let odd = addr & 0x01 != 0;
+        match (addr, odd) {
+            (0x6000..=0x7fff, _) if self.ram_enabled() && self.ram_protect & 0x40 == 0 => {
+                self.prg_ram[(addr & 0x1fff) as usize] = val;
+            }
+            (0x8000..=0x9fff, false) => self.bank_select = val,
+            (0x8000..=0x9fff, true) => self.banks[(self.bank_select & 0x07) as usize] = val,
+            (0xa000..=0xbfff, false) => self.horizontal = val & 0x01 != 0,
+            (0xa000..=0xbfff, true) => self.ram_protect = val,
+            (0xc000..=0xdfff, false) => self.irq_latch = val,
+            (0xc000..=0xdfff, true) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xe000..=0xffff, false) => {
+                self.irq_enabled = false;
+                self.irq = false;
+            }
+            (0xe000..=0xffff, true) => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        if !self.a12 {
+            self.a12_low = self.a12_low.saturating_add(1);
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, val);
+    }
+
+    // The IRQ counter is clocked on rising edges of A12 that follow a
+    // long enough low period
+    fn ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.a12 && self.a12_low >= A12_LOW_CYCLES {
+            self.clock_irq();
+        }
+        if !a12 && self.a12 {
+            self.a12_low = 0;
+        }
+        self.a12 = a12;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            Mirroring::FourScreen
+        } else if self.horizontal {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MMC3 with 256 KB PRG and 256 KB CHR, NES 2.0 if given a submapper.
+    // Every PRG byte holds its 8 KB bank number and every CHR byte its
+    // 1 KB bank number
+    fn mmc3(submapper: Option<u8>) -> Mmc3 {
+        let (prg, chr) = (0x40000, 0x40000);
+        let mut rom = vec![0; 16 + prg + chr];
+        rom[..8].copy_from_slice(b"NES\x1a\x10\x20\x40\x00");
+        if let Some(submapper) = submapper {
+            rom[7] = 0x08;
+            rom[8] = submapper << 4;
+        }
+        for (i, b) in rom[16..16 + prg].iter_mut().enumerate() {
+            *b = (i / PRG_BANK_SIZE) as u8;
+        }
+        for (i, b) in rom[16 + prg..].iter_mut().enumerate() {
+            *b = (i / CHR_BANK_SIZE) as u8;
+        }
+        Mmc3::new(Cartridge::new(&rom).unwrap())
+    }
+
+    fn set_bank(m: &mut Mmc3, mode: u8, reg: u8, val: u8) {
+        m.cpu_write(0x8000, mode | reg);
+        m.cpu_write(0x8001, val);
+    }
+
+    // A12 low for long enough, then high, like a line's sprite fetches
+    fn a12_rise(m: &mut Mmc3) {
+        m.ppu_address(0x0000);
+        for _ in 0..A12_LOW_CYCLES {
+            m.cpu_clock();
+        }
+        m.ppu_address(0x1000);
+    }
+
+    #[test]
+    fn prg_modes() {
+        let mut m = mmc3(None);
+        set_bank(&mut m, 0x00, 6, 5);
+        set_bank(&mut m, 0x00, 7, 9);
+        let banks = |m: &Mmc3| [0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.cpu_peek(a).unwrap());
+        assert_eq!(banks(&m), [5, 9, 30, 31]);
+        m.cpu_write(0x8000, 0x40);
+        assert_eq!(banks(&m), [30, 9, 5, 31]);
+    }
+
+    #[test]
+    fn chr_modes() {
+        let mut m = mmc3(None);
+        // R0 and R1 are 2 KB banks, the low bit is ignored
+        for (reg, val) in [(0, 5), (1, 6), (2, 10), (3, 11), (4, 12), (5, 13)] {
+            set_bank(&mut m, 0x00, reg, val);
+        }
+        let banks = |m: &mut Mmc3| (0..8).map(|i| m.ppu_read(i * 0x400)).collect::<Vec<_>>();
+        assert_eq!(banks(&mut m), [4, 5, 6, 7, 10, 11, 12, 13]);
+        m.cpu_write(0x8000, 0x80);
+        assert_eq!(banks(&mut m), [10, 11, 12, 13, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn mirroring_and_ram_protect() {
+        let mut m = mmc3(None);
+        assert_eq!(m.mirroring(), Mirroring::Vertical);
+        m.cpu_write(0xa000, 0x01);
+        assert_eq!(m.mirroring(), Mirroring::Horizontal);
+
+        m.cpu_write(0x6000, 0x55);
+        assert_eq!(m.cpu_peek(0x6000), Some(0x55));
+        // Write protected
+        m.cpu_write(0xa001, 0xc0);
+        m.cpu_write(0x6000, 0xaa);
+        assert_eq!(m.cpu_peek(0x6000), Some(0x55));
+        // Disabled
+        m.cpu_write(0xa001, 0x00);
+        assert_eq!(m.cpu_peek(0x6000), None);
+    }
+
+    #[test]
+    fn scanline_irq() {
+        let mut m = mmc3(None);
+        m.cpu_write(0xc000, 3);
+        m.cpu_write(0xc001, 0);
+        m.cpu_write(0xe001, 0);
+
+        // The first rise reloads the counter, the fourth takes it to 0
+        for _ in 0..3 {
+            a12_rise(&mut m);
+            assert!(!m.irq());
+        }
+        a12_rise(&mut m);
+        assert!(m.irq());
+
+        // $E000 acknowledges and disables
+        m.cpu_write(0xe000, 0);
+        assert!(!m.irq());
+        for _ in 0..4 {
+            a12_rise(&mut m);
+        }
+        assert!(!m.irq());
+    }
+
+    #[test]
+    fn a12_filter() {
+        let mut m = mmc3(None);
+        m.cpu_write(0xc000, 0);
+        m.cpu_write(0xc001, 0);
+        m.cpu_write(0xe001, 0);
+        // Rises after a short low period don't clock the counter
+        m.ppu_address(0x1000);
+        for _ in 0..8 {
+            m.ppu_address(0x0000);
+            m.cpu_clock();
+            m.ppu_address(0x1000);
+        }
+        assert!(!m.irq());
+        a12_rise(&mut m);
+        assert!(m.irq());
+    }
+
+    #[test]
+    fn zero_latch_revisions() {
+        // Latch 0 fires on every clock on MMC3C, only after a reload on MMC3A
+        let fires = |submapper| {
+            let mut m = mmc3(submapper);
+            m.cpu_write(0xc000, 0);
+            m.cpu_write(0xc001, 0);
+            m.cpu_write(0xe001, 0);
+            (0..3)
+                .map(|_| {
+                    a12_rise(&mut m);
+                    let irq = m.irq();
+                    m.cpu_write(0xe000, 0);
+                    m.cpu_write(0xe001, 0);
+                    irq
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(fires(None), [true, true, true]);
+        assert_eq!(fires(Some(4)), [true, false, false]);
+    }
+}
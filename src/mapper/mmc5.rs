@@ -0,0 +1,678 @@
+use super::{Chr, Mapper};
+use crate::apu::Pulse;
+use crate::cartridge::Cartridge;
+use crate::ppu::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const EXRAM_SIZE: usize = 0x0400;
+/// PRG RAM given to boards whose header asks for less, the most any
+/// MMC5 board has
+const PRG_RAM_SIZE: usize = 0x10000;
+/// CPU cycles without a PPU fetch before the frame is taken as over
+const IDLE_CYCLES: u8 = 3;
+/// Pattern fetches from the start of a scanline to its first sprite
+/// fetch, and the sprite fetches after that
+const BG_FETCHES: u8 = 64;
+const SPRITE_FETCHES: u8 = 16;
+/// Nametable fetch, counted from the start of a scanline, that fetches
+/// the next line's first tile
+const NEXT_LINE_FETCH: u8 = 32;
+/// CPU cycles between clocks of the expansion pulses' envelopes and
+/// length counters, 240 Hz
+const AUDIO_FRAME_CYCLES: u16 = 7457;
+
+/// Mapper 5, Nintendo MMC5. Four PRG and CHR banking modes, 1 KB of
+/// ExRAM, fill-mode and split-screen nametables, a scanline IRQ worked
+/// out by watching PPU fetches, a multiplier and expansion audio
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    exram: [u8; EXRAM_SIZE],
+    prg_mode: u8,
+    chr_mode: u8,
+    /// $5102 and $5103, PRG RAM is writable with 2 and 1 in them
+    ram_protect: [u8; 2],
+    /// 0: extra nametable, 1: extended attributes, 2: RAM, 3: ROM
+    exram_mode: u8,
+    /// Source of each nametable, 2 bits each: CIRAM page 0 or 1, ExRAM
+    /// or fill mode
+    nametables: u8,
+    fill_tile: u8,
+    fill_attr: u8,
+    /// $5113-$5117, RAM bank at $6000 then the four $8000-$FFFF banks
+    prg_banks: [u8; 5],
+    /// $5120-$5127 for sprites, $5128-$512B for background in 8x16
+    /// sprite mode. The upper bits from $5130 are added when written
+    chr_banks: [u16; 12],
+    chr_upper: u8,
+    /// $5128-$512B were written last, 8x8 sprite mode uses them for
+    /// everything
+    chr_b_last: bool,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    /// Split tile column and split Y of the tile being fetched
+    split: Option<(u8, u8)>,
+    /// ExRAM byte of the tile being fetched, in extended attribute mode
+    ex_attr: u8,
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    /// The PPU is fetching a visible frame
+    in_frame: bool,
+    scanline: u8,
+    /// CPU cycles since the last PPU fetch
+    idle: u8,
+    /// Last nametable address read and how many times in a row since,
+    /// the third read in a row marks the start of a scanline
+    last_nametable: u16,
+    repeats: u8,
+    /// Nametable and pattern fetches since the start of the scanline
+    tile_fetches: u8,
+    pattern_fetches: u8,
+    /// Snooped from $2000 and $2001
+    sprites_16: bool,
+    rendering: bool,
+    multiplicand: u8,
+    multiplier: u8,
+    audio: Audio,
+}
+
+impl Mmc5 {
+    pub fn new(cart: Cartridge) -> Self {
+        let header = &cart.header;
+        let ram_size = (header.prg_ram_size + header.prg_nvram_size).max(PRG_RAM_SIZE);
+        Self {
+            chr: Chr::new(header, cart.chr_rom),
+            prg_rom: cart.prg_rom,
+            prg_ram: vec![0; ram_size],
+            exram: [0; EXRAM_SIZE],
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attr: 0,
+            prg_banks: [0, 0xff, 0xff, 0xff, 0xff],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            chr_b_last: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            split: None,
+            ex_attr: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            idle: 0,
+            last_nametable: 0,
+            repeats: 0,
+            tile_fetches: 0,
+            pattern_fetches: 0,
+            sprites_16: false,
+            rendering: false,
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            audio: Audio::new(),
+        }
+    }
+
+    // Where a CPU address in $8000-$FFFF goes: the bank register, and
+    // the offset into PRG ROM or PRG RAM
+    fn prg_target(&self, addr: u16) -> (bool, usize) {
+        let slot = ((addr >> 13) & 0x03) as usize;
+        // Register and its bank size in 8 KB banks
+        let (reg, size) = match (self.prg_mode, slot) {
+            (0, _) => (4, 4),
+            (1, 0 | 1) | (2, 0 | 1) => (2, 2),
+            (1, _) => (4, 2),
+            (2, 2) => (3, 1),
+            (_, slot) => (slot + 1, 1),
+        };
+        let val = self.prg_banks[reg];
+        let bank = ((val & 0x7f) as usize & !(size - 1)) | (slot & (size - 1));
+        let offset = (addr & 0x1fff) as usize;
+        // $5117 always maps ROM, the others pick with bit 7
+        if reg == 4 || val & 0x80 != 0 {
+            (true, (bank * PRG_BANK_SIZE + offset) % self.prg_rom.len())
+        } else {
+            (false, self.ram_offset(bank as u8, addr))
+        }
+    }
+
+    fn ram_offset(&self, bank: u8, addr: u16) -> usize {
+        ((bank & 0x07) as usize * PRG_BANK_SIZE + (addr & 0x1fff) as usize) % self.prg_ram.len()
+    }
+
+    fn ram_writable(&self) -> bool {
+        self.ram_protect == [0x02, 0x01]
+    }
+
+    fn chr_offset(&self, addr: u16, set_b: bool) -> usize {
+        let size = CHR_BANK_SIZE << (3 - self.chr_mode);
+        let slot = addr as usize / size;
+        let reg = if set_b {
+            // Set B covers 4 KB, repeated in both halves
+            8 + match self.chr_mode {
+                3 => slot & 0x03,
+                2 => (slot & 0x01) * 2 + 1,
+                _ => 3,
+            }
+        } else {
+            (slot + 1) * (8 >> self.chr_mode) - 1
+        };
+        self.chr_banks[reg] as usize * size + addr as usize % size
+    }
+
+    // Fetches are being replaced by split and extended attribute data
+    fn substituting(&self) -> bool {
+        self.in_frame && self.rendering
+    }
+
+    // Three reads in a row of the same nametable address: the PPU's
+    // dummy fetches at the end of a line, a new scanline is starting
+    fn scanline_start(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+        self.tile_fetches = 0;
+        self.pattern_fetches = 0;
+    }
+
+    // Background tile fetch, picks up the ExRAM byte in extended attribute
+    // mode and returns the split tile when the tile is inside the split
+    fn fetch_tile(&mut self, addr: u16) -> Option<u8> {
+        let fetch = self.tile_fetches;
+        self.tile_fetches = self.tile_fetches.saturating_add(1);
+        self.ex_attr = self.exram[(addr & 0x03ff) as usize];
+        self.split = None;
+
+        // The first three fetches of this line happened at the end of the
+        // last one, the fetch at dot 257 is garbage
+        let (tile, line) = match fetch {
+            0..=30 => (fetch + 3, self.scanline),
+            NEXT_LINE_FETCH.. => (fetch - NEXT_LINE_FETCH, self.scanline.wrapping_add(1)),
+            _ => return None,
+        };
+        if self.split_control & 0x80 == 0 || self.exram_mode >= 2 {
+            return None;
+        }
+        let threshold = self.split_control & 0x1f;
+        let inside = if self.split_control & 0x40 != 0 {
+            tile >= threshold
+        } else {
+            tile < threshold
+        };
+        if !inside {
+            return None;
+        }
+
+        let y = ((u16::from(self.split_scroll) + u16::from(line)) % 240) as u8;
+        let x = tile & 0x1f;
+        self.split = Some((x, y));
+        Some(self.exram[(y as usize / 8) * 32 + x as usize])
+    }
+
+    // Attribute byte for the split tile, its 2 bits copied to all four
+    // quadrants
+    fn split_attribute(&self, x: u8, y: u8) -> u8 {
+        let attr = self.exram[0x3c0 + (y as usize / 32) * 8 + x as usize / 4];
+        let shift = ((y / 16) & 0x01) * 4 + ((x / 2) & 0x01) * 2;
+        ((attr >> shift) & 0x03) * 0x55
+    }
+
+    // Nametable byte from the source $5105 picks, None for CIRAM
+    fn nametable(&self, addr: u16) -> Option<u8> {
+        let source = (self.nametables >> (((addr >> 10) & 0x03) * 2)) & 0x03;
+        match source {
+            2 if self.exram_mode <= 1 => Some(self.exram[(addr & 0x03ff) as usize]),
+            2 => Some(0),
+            3 if addr & 0x03ff >= 0x03c0 => Some(self.fill_attr * 0x55),
+            3 => Some(self.fill_tile),
+            _ => None,
+        }
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5010 => Some(u8::from(self.audio.pcm_irq) << 7),
+            0x5015 => Some(self.audio.status()),
+            0x5204 => Some(u8::from(self.irq_pending) << 7 | u8::from(self.in_frame) << 6),
+            0x5205 => Some((u16::from(self.multiplicand) * u16::from(self.multiplier)) as u8),
+            0x5206 => {
+                Some(((u16::from(self.multiplicand) * u16::from(self.multiplier)) >> 8) as u8)
+            }
+            0x5c00..=0x5fff if self.exram_mode >= 2 => Some(self.exram[(addr & 0x03ff) as usize]),
+            0x6000..=0x7fff => Some(self.prg_ram[self.ram_offset(self.prg_banks[0], addr)]),
+            0x8000..=0xffff => match self.prg_target(addr) {
+                (true, offset) => Some(self.prg_rom[offset]),
+                (false, offset) => Some(self.prg_ram[offset]),
+            },
+            _ => None,
+        }
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        let val = self.cpu_peek(addr);
+        match addr {
+            0x5010 => self.audio.pcm_irq = false,
+            0x5204 => self.irq_pending = false,
+            0x8000..=0xbfff => self.audio.pcm_read(val.unwrap_or(0)),
+            _ => {}
+        }
+        val
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x5000..=0x5015 => self.audio.write(addr, val),
+            0x5100 => self.prg_mode = val & 0x03,
+            0x5101 => self.chr_mode = val & 0x03,
+            0x5102 | 0x5103 => self.ram_protect[(addr & 0x01) as usize] = val & 0x03,
+            0x5104 => self.exram_mode = val & 0x03,
+            0x5105 => self.nametables = val,
+            0x5106 => self.fill_tile = val,
+            0x5107 => self.fill_attr = val & 0x03,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = val,
+            0x5120..=0x512b => {
+                let reg = (addr - 0x5120) as usize;
+                self.chr_banks[reg] = u16::from(val) | u16::from(self.chr_upper) << 8;
+                self.chr_b_last = reg >= 8;
+            }
+            0x5130 => self.chr_upper = val & 0x03,
+            0x5200 => self.split_control = val,
+            0x5201 => self.split_scroll = val,
+            0x5202 => self.split_bank = val,
+            0x5203 => self.irq_compare = val,
+            0x5204 => self.irq_enabled = val & 0x80 != 0,
+            0x5205 => self.multiplicand = val,
+            0x5206 => self.multiplier = val,
+            // Nametable modes only take writes while rendering, at other
+            // times they write 0
+            0x5c00..=0x5fff if self.exram_mode <= 1 => {
+                let val = if self.in_frame { val } else { 0 };
+                self.exram[(addr & 0x03ff) as usize] = val;
+            }
+            0x5c00..=0x5fff if self.exram_mode == 2 => self.exram[(addr & 0x03ff) as usize] = val,
+            0x6000..=0x7fff if self.ram_writable() => {
+                let offset = self.ram_offset(self.prg_banks[0], addr);
+                self.prg_ram[offset] = val;
+            }
+            0x8000..=0xdfff if self.ram_writable() => {
+                if let (false, offset) = self.prg_target(addr) {
+                    self.prg_ram[offset] = val;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        self.idle = self.idle.saturating_add(1);
+        if self.idle >= IDLE_CYCLES {
+            self.in_frame = false;
+        }
+        self.audio.clock();
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let fetch = self.pattern_fetches;
+        self.pattern_fetches = self.pattern_fetches.saturating_add(1);
+        if !self.substituting() {
+            return self.chr.read(self.chr_offset(addr, self.chr_b_last));
+        }
+
+        let sprite = (BG_FETCHES..BG_FETCHES + SPRITE_FETCHES).contains(&fetch);
+        if !sprite {
+            if let Some((_, y)) = self.split {
+                let offset = (addr & 0x0ff8) as usize | (y & 0x07) as usize;
+                return self.chr.read(self.split_bank as usize * 0x1000 + offset);
+            }
+            if self.exram_mode == 1 {
+                let bank = (self.chr_upper as usize) << 6 | (self.ex_attr & 0x3f) as usize;
+                return self.chr.read(bank * 0x1000 + (addr & 0x0fff) as usize);
+            }
+        }
+        let set_b = if self.sprites_16 {
+            !sprite
+        } else {
+            self.chr_b_last
+        };
+        self.chr.read(self.chr_offset(addr, set_b))
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let offset = self.chr_offset(addr, self.chr_b_last);
+        self.chr.write(offset, val);
+    }
+
+    fn nametable_read(&mut self, addr: u16) -> Option<u8> {
+        if addr == self.last_nametable {
+            self.repeats = self.repeats.saturating_add(1);
+            if self.repeats == 2 {
+                self.scanline_start();
+            }
+        } else {
+            self.last_nametable = addr;
+            self.repeats = 0;
+        }
+
+        // Repeated reads are the dummy fetches, they leave the tile alone
+        if self.substituting() && self.repeats == 0 {
+            if addr & 0x03ff >= 0x03c0 {
+                if let Some((x, y)) = self.split {
+                    return Some(self.split_attribute(x, y));
+                }
+                if self.exram_mode == 1 {
+                    return Some((self.ex_attr >> 6) * 0x55);
+                }
+            } else if let Some(tile) = self.fetch_tile(addr) {
+                return Some(tile);
+            }
+        }
+        self.nametable(addr)
+    }
+
+    fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        self.nametable(addr)
+    }
+
+    fn nametable_write(&mut self, addr: u16, val: u8) -> bool {
+        let source = (self.nametables >> (((addr >> 10) & 0x03) * 2)) & 0x03;
+        match source {
+            2 if self.exram_mode <= 1 => {
+                self.exram[(addr & 0x03ff) as usize] = val;
+                true
+            }
+            2 | 3 => true,
+            _ => false,
+        }
+    }
+
+    fn ppu_register_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x2000 => self.sprites_16 = val & 0x20 != 0,
+            0x2001 => self.rendering = val & 0x18 != 0,
+            _ => {}
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn ppu_address(&mut self, _addr: u16) {
+        self.idle = 0;
+    }
+
+    // ExRAM and fill mode nametables are handled in nametable_read, the
+    // page given for them doesn't matter
+    fn mirroring(&self) -> Mirroring {
+        let page = |n: u8| (self.nametables >> (n * 2)) & 0x01;
+        Mirroring::PerNametable([page(0), page(1), page(2), page(3)])
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || self.audio.irq()
+    }
+}
+
+/// MMC5 expansion audio: two pulses like the APU's without sweep units,
+/// and an 8 bit PCM channel
+struct Audio {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    pcm: u8,
+    /// PCM plays bytes the CPU reads from $8000-$BFFF instead of $5011
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    /// Set by a 0 byte in read mode
+    pcm_irq: bool,
+    odd_cycle: bool,
+    /// CPU cycles to the next envelope and length counter clock
+    frame_timer: u16,
+}
+
+impl Audio {
+    fn new() -> Self {
+        Self {
+            pulse1: Pulse::without_sweep(),
+            pulse2: Pulse::without_sweep(),
+            pcm: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq: false,
+            odd_cycle: false,
+            frame_timer: AUDIO_FRAME_CYCLES,
+        }
+    }
+
+    // $5000-$5015
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x5000..=0x5003 => self.pulse1.write(addr & 0x03, val),
+            0x5004..=0x5007 => self.pulse2.write(addr & 0x03, val),
+            0x5010 => {
+                self.pcm_read_mode = val & 0x01 != 0;
+                self.pcm_irq_enabled = val & 0x80 != 0;
+            }
+            // 0 can't be written, it's the end marker in read mode
+            0x5011 if !self.pcm_read_mode && val != 0 => self.pcm = val,
+            0x5015 => {
+                self.pulse1.length.set_enabled(val & 0x01 != 0);
+                self.pulse2.length.set_enabled(val & 0x02 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    // $5015 read, which length counters are running
+    fn status(&self) -> u8 {
+        u8::from(self.pulse1.length.active()) | u8::from(self.pulse2.length.active()) << 1
+    }
+
+    // CPU read of $8000-$BFFF, played in read mode. A 0 fires the IRQ
+    fn pcm_read(&mut self, val: u8) {
+        if !self.pcm_read_mode {
+            return;
+        }
+        if val == 0 {
+            self.pcm_irq = true;
+        } else {
+            self.pcm = val;
+        }
+    }
+
+    // Run for one CPU cycle
+    fn clock(&mut self) {
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        self.frame_timer -= 1;
+        if self.frame_timer == 0 {
+            self.frame_timer = AUDIO_FRAME_CYCLES;
+            for pulse in [&mut self.pulse1, &mut self.pulse2] {
+                pulse.envelope.clock();
+                pulse.length.clock();
+            }
+        }
+    }
+
+    // Mixed like the APU pulses and DMC
+    fn output(&self) -> f32 {
+        let pulse = f32::from(self.pulse1.output() + self.pulse2.output());
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+        let pcm = f32::from(self.pcm) / 2.0 / 22638.0;
+        let pcm_out = if pcm == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / pcm + 100.0)
+        };
+        pulse_out + pcm_out
+    }
+
+    fn irq(&self) -> bool {
+        self.pcm_irq && self.pcm_irq_enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::Console;
+
+    // 32 KB PRG, 8 KB CHR MMC5 image with the program at $E000
+    fn rom(program: &[u8], irq: u16) -> Vec<u8> {
+        let mut rom = vec![0; 16 + 0x8000 + 0x2000];
+        rom[..8].copy_from_slice(b"NES\x1a\x02\x01\x50\x00");
+        let bank = 16 + 0x6000;
+        rom[bank..bank + program.len()].copy_from_slice(program);
+        let [lo, hi] = irq.to_le_bytes();
+        rom[bank + 0x1ffa..bank + 0x2000].copy_from_slice(&[0x00, 0xe0, 0x00, 0xe0, lo, hi]);
+        rom
+    }
+
+    #[test]
+    fn scanline_irq_is_stable_across_frames() {
+        #[rustfmt::skip]
+        let program = [
+            0xa9, 0x40, 0x8d, 0x17, 0x40, // LDA #$40, STA $4017
+            0xa9, 0x0a, 0x8d, 0x03, 0x52, // LDA #10, STA $5203
+            0xa9, 0x80, 0x8d, 0x04, 0x52, // LDA #$80, STA $5204
+            0xa9, 0x18, 0x8d, 0x01, 0x20, // LDA #$18, STA $2001
+            0x58,                         // CLI
+            0x4c, 0x15, 0xe0,             // JMP *
+            0xad, 0x04, 0x52,             // IRQ: LDA $5204
+            0xe6, 0x00,                   // INC $00
+            0x40,                         // RTI
+        ];
+        let cart = Cartridge::new(&rom(&program, 0xe018)).unwrap();
+        let mut console = Console::new(cart).unwrap();
+        console.run_frame();
+
+        let mut lines = Vec::new();
+        let mut count = console.mem.peek8(0);
+        while lines.len() < 8 {
+            console.cpu.step(&mut console.mem);
+            if console.mem.peek8(0) != count {
+                count = console.mem.peek8(0);
+                lines.push(console.mem.ppu_position().0);
+            }
+        }
+        assert!(lines.iter().all(|&line| line == lines[0]), "{lines:?}");
+    }
+
+    #[test]
+    fn scanline_irq_on_compare_line() {
+        #[rustfmt::skip]
+        let program = [
+            0xa9, 0x40, 0x8d, 0x17, 0x40, // LDA #$40, STA $4017
+            0xa9, 0x64, 0x8d, 0x03, 0x52, // LDA #100, STA $5203
+            0xa9, 0x80, 0x8d, 0x04, 0x52, // LDA #$80, STA $5204
+            0xa9, 0x18, 0x8d, 0x01, 0x20, // LDA #$18, STA $2001
+            0x4c, 0x14, 0xe0,             // JMP *
+        ];
+        let cart = Cartridge::new(&rom(&program, 0xe000)).unwrap();
+        let mut console = Console::new(cart).unwrap();
+        console.run_frame();
+        for _ in 0..3 {
+            // Acknowledge, then wait for the next one with I set
+            console.mem.read8(0x5204);
+            while !console.mem.irq() {
+                console.cpu.step(&mut console.mem);
+            }
+            assert_eq!(console.mem.ppu_position().0, 100);
+        }
+    }
+
+    // MMC5 with 32 KB CHR, every CHR byte holds its 4 KB bank number.
+    // Rendering is on and a scanline has just started
+    fn rendering_mmc5() -> Mmc5 {
+        let mut image = rom(&[], 0xe000);
+        image[5] = 4;
+        image.resize(16 + 0x8000 + 0x8000, 0);
+        for (i, b) in image[16 + 0x8000..].iter_mut().enumerate() {
+            *b = (i / 0x1000) as u8;
+        }
+        let mut m = Mmc5::new(Cartridge::new(&image).unwrap());
+        m.ppu_register_write(0x2001, 0x18);
+        m.cpu_write(0x5101, 1);
+        for _ in 0..3 {
+            m.nametable_read(0x2000);
+        }
+        m
+    }
+
+    #[test]
+    fn extended_attributes() {
+        let mut m = rendering_mmc5();
+        m.cpu_write(0x5104, 1);
+        m.cpu_write(0x5c05, 0xc2);
+        m.cpu_write(0x5c06, 0x43);
+
+        // Palette from the top 2 bits, 4 KB CHR bank from the rest
+        assert_eq!(m.nametable_read(0x2005), None);
+        assert_eq!(m.nametable_read(0x23c1), Some(0xff));
+        assert_eq!(m.ppu_read(0x0010), 2);
+        assert_eq!(m.ppu_read(0x0018), 2);
+        assert_eq!(m.nametable_read(0x2006), None);
+        assert_eq!(m.nametable_read(0x23c1), Some(0x55));
+        assert_eq!(m.ppu_read(0x1020), 3);
+        assert_eq!(m.ppu_read(0x1028), 3);
+
+        // Outside rendering the attribute table reads through
+        m.ppu_register_write(0x2001, 0);
+        assert_eq!(m.nametable_read(0x23c1), None);
+        assert_eq!(m.ppu_read(0x0010), 0);
+    }
+
+    #[test]
+    fn split_screen() {
+        let mut m = rendering_mmc5();
+        // Split right of tile 4, scrolled 16 lines down, CHR from 4 KB
+        // bank 5
+        m.cpu_write(0x5200, 0xc4);
+        m.cpu_write(0x5201, 16);
+        m.cpu_write(0x5202, 5);
+        m.cpu_write(0x5c00 + 2 * 32 + 4, 0x42);
+        m.cpu_write(0x5c00 + 0x3c1, 0x30);
+
+        // Fetch 0 is tile 3, left of the split
+        assert_eq!(m.nametable_read(0x2003), None);
+        assert_eq!(m.nametable_read(0x23c0), None);
+        assert_eq!(m.ppu_read(0x0420), 0);
+        assert_eq!(m.ppu_read(0x0428), 0);
+
+        // Tile 4 comes from ExRAM at the split's own scroll
+        assert_eq!(m.nametable_read(0x2004), Some(0x42));
+        assert_eq!(m.nametable_read(0x23c1), Some(0xff));
+        assert_eq!(m.ppu_read(0x0427), 5);
+        assert_eq!(m.ppu_read(0x042f), 5);
+
+        // CPU reads see the real nametable and don't count as fetches
+        assert_eq!(m.nametable_peek(0x2005), None);
+        assert_eq!(m.nametable_read(0x2005), Some(0));
+        assert_eq!(m.nametable_read(0x23c1), Some(0xff));
+    }
+}
@@ -0,0 +1,240 @@
+mod axrom;
+mod bnrom;
+mod chr;
+mod cnrom;
+mod gxrom;
+mod mmc1;
+mod mmc2;
+mod mmc3;
+mod mmc5;
+mod nrom;
+mod uxrom;
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::cartridge::{Cartridge, RomError, RomHeader};
+use crate::ppu::Mirroring;
+use axrom::Axrom;
+use bnrom::Bnrom;
+use chr::Chr;
+use cnrom::Cnrom;
+use gxrom::Gxrom;
+use mmc1::Mmc1;
+use mmc2::Mmc2;
+use mmc3::Mmc3;
+use mmc5::Mmc5;
+use nrom::Nrom;
+use uxrom::Uxrom;
+
+/// Cartridge board logic sitting between the CPU/PPU buses and the ROM chips
+pub trait Mapper {
+    /// CPU read in $4020-$FFFF without side effects, None if nothing
+    /// drives the bus
+    fn cpu_peek(&self, addr: u16) -> Option<u8>;
+
+    /// CPU read in $4020-$FFFF, mappers with read side effects override this
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    /// CPU write in $4020-$FFFF
+    fn cpu_write(&mut self, addr: u16, val: u8);
+
+    /// Called once every CPU cycle, before the cycle's bus access
+    fn cpu_clock(&mut self) {}
+
+    /// PPU pattern table read in $0000-$1FFF
+    fn ppu_read(&mut self, addr: u16) -> u8;
+
+    /// PPU pattern table write in $0000-$1FFF
+    fn ppu_write(&mut self, addr: u16, val: u8);
+
+    /// Nametable fetch while rendering in $2000-$2FFF, None to read CIRAM
+    /// as mirrored
+    fn nametable_read(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    /// Nametable read through $2007 in $2000-$2FFF, None to read CIRAM as
+    /// mirrored. Not a rendering fetch, boards counting fetches ignore it
+    fn nametable_peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    /// Nametable write in $2000-$2FFF, false to write CIRAM as mirrored
+    fn nametable_write(&mut self, _addr: u16, _val: u8) -> bool {
+        false
+    }
+
+    /// CPU write to a PPU register, $2000-$2007
+    fn ppu_register_write(&mut self, _addr: u16, _val: u8) {}
+
+    /// Expansion audio level, mixed into the APU output
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// Address the PPU put on its bus, for every fetch and $2006/$2007
+    /// access. Boards watching A12 hook in here
+    fn ppu_address(&mut self, _addr: u16) {}
+
+    /// Current nametable mirroring
+    fn mirroring(&self) -> Mirroring;
+
+    /// State of the cartridge IRQ line, true while asserted
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+/// Mapper shared between the CPU and PPU buses
+pub type MapperRef = Rc<RefCell<dyn Mapper>>;
+
+// Build the mapper described by the cartridge header
+pub fn new(cart: Cartridge) -> Result<MapperRef, RomError> {
+    match cart.header.mapper {
+        0 => Ok(Rc::new(RefCell::new(Nrom::new(cart)))),
+        1 | 155 => Ok(Rc::new(RefCell::new(Mmc1::new(cart)))),
+        2 => Ok(Rc::new(RefCell::new(Uxrom::new(cart)))),
+        3 => Ok(Rc::new(RefCell::new(Cnrom::new(cart)))),
+        4 => Ok(Rc::new(RefCell::new(Mmc3::new(cart)))),
+        5 => Ok(Rc::new(RefCell::new(Mmc5::new(cart)))),
+        7 => Ok(Rc::new(RefCell::new(Axrom::new(cart)))),
+        9 | 10 => Ok(Rc::new(RefCell::new(Mmc2::new(cart)))),
+        11 | 66 => Ok(Rc::new(RefCell::new(Gxrom::new(cart)))),
+        34 => Ok(Rc::new(RefCell::new(Bnrom::new(cart)))),
+        n => Err(RomError::UnsupportedMapper(n)),
+    }
+}
+
+// Whether a discrete board has bus conflicts: ROM drives the data bus
+// during register writes, so the register sees the written value ANDed
+// with the ROM byte. On mappers 2, 3 and 7 NES 2.0 submapper 1 says no
+// and 2 says yes
+fn bus_conflicts(header: &RomHeader, default: bool) -> bool {
+    match header.submapper {
+        1 => false,
+        2 => true,
+        _ => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mapper for an image with the given 16 KB PRG and 8 KB CHR bank
+    // counts, NES 2.0 if given a submapper. Every PRG byte holds its 4 KB
+    // page number except the last of each page, which is $FF so writes
+    // there don't see bus conflicts. Every CHR byte holds its 1 KB page
+    fn board(mapper: u8, prg: u8, chr: u8, submapper: Option<u8>) -> MapperRef {
+        let prg_len = prg as usize * 0x4000;
+        let chr_len = chr as usize * 0x2000;
+        let mut rom = vec![0; 16 + prg_len + chr_len];
+        rom[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1a, prg, chr, mapper << 4, mapper & 0xf0]);
+        if let Some(submapper) = submapper {
+            rom[7] |= 0x08;
+            rom[8] = submapper << 4;
+        }
+        for (i, b) in rom[16..16 + prg_len].iter_mut().enumerate() {
+            *b = if i & 0xfff == 0xfff {
+                0xff
+            } else {
+                (i / 0x1000) as u8
+            };
+        }
+        for (i, b) in rom[16 + prg_len..].iter_mut().enumerate() {
+            *b = (i / 0x400) as u8;
+        }
+        new(Cartridge::new(&rom).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn uxrom() {
+        let unrom = |submapper| board(2, 8, 0, submapper);
+        let mapper = unrom(None);
+        let mut m = mapper.borrow_mut();
+        m.cpu_write(0x8fff, 3);
+        assert_eq!(m.cpu_peek(0x8000), Some(12));
+        assert_eq!(m.cpu_peek(0xc000), Some(28));
+        // $8000 holds page 12, the write is ANDed with it
+        m.cpu_write(0x8000, 0x05);
+        assert_eq!(m.cpu_peek(0x8000), Some(16));
+
+        // Submapper 1 has no bus conflicts
+        let mapper = unrom(Some(1));
+        let mut m = mapper.borrow_mut();
+        m.cpu_write(0x8fff, 3);
+        m.cpu_write(0x8000, 0x05);
+        assert_eq!(m.cpu_peek(0x8000), Some(20));
+    }
+
+    #[test]
+    fn cnrom() {
+        let mapper = board(3, 2, 4, None);
+        let mut m = mapper.borrow_mut();
+        assert_eq!(m.ppu_read(0x1c00), 7);
+        m.cpu_write(0x8fff, 2);
+        assert_eq!(m.ppu_read(0x0000), 16);
+        assert_eq!(m.ppu_read(0x1c00), 23);
+        // CHR ROM ignores writes
+        m.ppu_write(0x0000, 0x55);
+        assert_eq!(m.ppu_read(0x0000), 16);
+    }
+
+    #[test]
+    fn axrom() {
+        let mapper = board(7, 16, 0, None);
+        let mut m = mapper.borrow_mut();
+        assert_eq!(m.mirroring(), Mirroring::SingleScreenLower);
+        m.cpu_write(0x8fff, 0x13);
+        assert_eq!(m.cpu_peek(0x8000), Some(24));
+        assert_eq!(m.cpu_peek(0xf000), Some(31));
+        assert_eq!(m.mirroring(), Mirroring::SingleScreenUpper);
+        // CHR RAM
+        m.ppu_write(0x0000, 0x55);
+        assert_eq!(m.ppu_read(0x0000), 0x55);
+    }
+
+    #[test]
+    fn gxrom_and_color_dreams() {
+        let mapper = board(66, 8, 4, None);
+        let mut m = mapper.borrow_mut();
+        m.cpu_write(0x8fff, 0x21);
+        assert_eq!(m.cpu_peek(0x8000), Some(16));
+        assert_eq!(m.ppu_read(0x0000), 8);
+        // $8000 holds page 16, the write is ANDed with it
+        m.cpu_write(0x8000, 0x33);
+        assert_eq!(m.cpu_peek(0x8000), Some(8));
+        assert_eq!(m.ppu_read(0x0000), 0);
+
+        let mapper = board(11, 8, 4, None);
+        let mut m = mapper.borrow_mut();
+        m.cpu_write(0x8fff, 0x12);
+        assert_eq!(m.cpu_peek(0x8000), Some(16));
+        assert_eq!(m.ppu_read(0x0000), 8);
+    }
+
+    #[test]
+    fn bnrom_and_nina() {
+        let mapper = board(34, 8, 0, None);
+        let mut m = mapper.borrow_mut();
+        m.cpu_write(0x8fff, 2);
+        assert_eq!(m.cpu_peek(0x8000), Some(16));
+        assert_eq!(m.cpu_peek(0x6000), None);
+
+        // More than 8 KB of CHR makes it NINA-001
+        let mapper = board(34, 4, 2, None);
+        let mut m = mapper.borrow_mut();
+        m.cpu_write(0x7ffd, 1);
+        m.cpu_write(0x7ffe, 3);
+        m.cpu_write(0x7fff, 2);
+        assert_eq!(m.cpu_peek(0x8000), Some(8));
+        assert_eq!(m.ppu_read(0x0000), 12);
+        assert_eq!(m.ppu_read(0x1000), 8);
+        assert_eq!(m.cpu_peek(0x7ffd), Some(1));
+        // $8000 writes do nothing on NINA-001
+        m.cpu_write(0x8fff, 0);
+        assert_eq!(m.cpu_peek(0x8000), Some(8));
+    }
+}
@@ -0,0 +1,61 @@
+use super::{Chr, Mapper};
+use crate::cartridge::Cartridge;
+use crate::ppu::Mirroring;
+
+const PRG_RAM_SIZE: usize = 0x2000;
+
+/// Mapper 0, fixed 16 or 32 KB PRG ROM and 8 KB CHR
+pub struct Nrom {
+    /// PRG ROM, 16 KB carts are mirrored into $C000
+    prg_rom: Vec<u8>,
+    /// PRG RAM at $6000, only used by Family Basic
+    prg_ram: Vec<u8>,
+    /// CHR ROM or RAM
+    chr: Chr,
+    /// Hardwired mirroring
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(cart: Cartridge) -> Self {
+        let mut prg_ram = vec![0; PRG_RAM_SIZE];
+        if let Some(trainer) = &cart.trainer {
+            prg_ram[0x1000..0x1000 + trainer.len()].copy_from_slice(trainer);
+        }
+
+        Self {
+            prg_rom: cart.prg_rom,
+            prg_ram,
+            chr: Chr::new(&cart.header, cart.chr_rom),
+            mirroring: cart.header.mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff => Some(self.prg_ram[(addr & 0x1fff) as usize]),
+            0x8000..=0xffff => Some(self.prg_rom[(addr & 0x7fff) as usize % self.prg_rom.len()]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        if let 0x6000..=0x7fff = addr {
+            self.prg_ram[(addr & 0x1fff) as usize] = val;
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        self.chr.write(addr as usize, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
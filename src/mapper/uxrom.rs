@@ -0,0 +1,61 @@
+use super::{bus_conflicts, Chr, Mapper};
+use crate::cartridge::Cartridge;
+use crate::ppu::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// Mapper 2, UNROM/UOROM. Switchable 16 KB at $8000, last bank fixed at
+/// $C000
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    bank: u8,
+}
+
+impl Uxrom {
+    pub fn new(cart: Cartridge) -> Self {
+        Self {
+            bus_conflicts: bus_conflicts(&cart.header, true),
+            chr: Chr::new(&cart.header, cart.chr_rom),
+            mirroring: cart.header.mirroring,
+            prg_rom: cart.prg_rom,
+            bank: 0,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let bank = match addr {
+            0x8000..=0xbfff => self.bank as usize,
+            0xc000..=0xffff => (self.prg_rom.len() / PRG_BANK_SIZE).saturating_sub(1),
+            _ => return None,
+        };
+        let offset = bank * PRG_BANK_SIZE + (addr & 0x3fff) as usize;
+        Some(self.prg_rom[offset % self.prg_rom.len()])
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        if addr >= 0x8000 {
+            self.bank = if self.bus_conflicts {
+                val & self.cpu_peek(addr).unwrap_or(0xff)
+            } else {
+                val
+            };
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        self.chr.write(addr as usize, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
@@ -1,58 +1,312 @@
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell};
 
-use crate::ppu::{Mirroring, Ppu};
+use crate::apu::Apu;
+use crate::controller::{Buttons, Controller};
+use crate::mapper::MapperRef;
+use crate::ppu::palette::Pixel;
+use crate::ppu::Ppu;
 
 const RAM_SIZE: usize = 0x800;
+const OAM_DMA_LEN: u16 = 256;
 
 pub struct Memory {
     ram: [u8; RAM_SIZE],
     ppu: RefCell<Ppu>,
+    apu: Apu,
+    controllers: [Controller; 2],
+    mapper: MapperRef,
+    /// Last value on the CPU data bus, what reads of undriven addresses see
+    open_bus: u8,
+    /// CPU cycles since power-on
+    cycles: u64,
+    /// NMI edge latched from the PPU, waiting for the CPU
+    nmi: bool,
+    /// NMI latch as of the end of the previous cycle
+    nmi_prev: bool,
+    /// IRQ line as of the end of the previous cycle
+    irq_prev: bool,
 }
 
 impl Memory {
-    pub fn new(char_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+    pub fn new(mapper: MapperRef) -> Self {
         Self {
             ram: [0; RAM_SIZE],
-            ppu: RefCell::new(Ppu::new(char_rom, mirroring)),
+            ppu: RefCell::new(Ppu::new(mapper.clone())),
+            apu: Apu::new(),
+            controllers: Default::default(),
+            mapper,
+            open_bus: 0,
+            cycles: 0,
+            nmi: false,
+            nmi_prev: false,
+            irq_prev: false,
         }
     }
 
-    pub fn read8(&self, addr: u16) -> u8 {
-        match addr {
+    // CPU read, takes one cycle
+    pub fn read8(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.read_bus(addr)
+    }
+
+    // What a read puts on the bus, with its side effects, without taking
+    // a cycle
+    fn read_bus(&mut self, addr: u16) -> u8 {
+        let val = match addr {
             // 2 KB internam RAM mirrors
             0x0000..=0x1fff => self.ram[(addr & 0x7ff) as usize],
-            // PPU Status register
-            0x2002 => self.ppu.borrow_mut().read_stat(),
-            // PPU OAM data
-            0x2004 => self.ppu.borrow().read_oam(),
-            // PPU Data register
-            0x2007 => self.ppu.borrow_mut().read_vram(),
-            _ => 0,
+            // PPU registers, mirrored every 8 bytes
+            0x2000..=0x3fff => match addr & 0x2007 {
+                // PPU Status register
+                0x2002 => self.ppu.borrow_mut().read_stat(),
+                // PPU OAM data
+                0x2004 => self.ppu.borrow_mut().read_oam(),
+                // PPU Data register
+                0x2007 => self.ppu.borrow_mut().read_vram(),
+                // Write only registers
+                _ => self.ppu.borrow().read_latch(),
+            },
+            // APU status, read inside the CPU so the bus isn't driven and
+            // bit 5 is left floating
+            0x4015 => return (self.apu.read_status() & !0x20) | (self.open_bus & 0x20),
+            // Controllers, only the low bits are driven
+            0x4016 | 0x4017 => {
+                let port = (addr & 0x01) as usize;
+                (self.open_bus & 0xe0) | self.controllers[port].read()
+            }
+            // Cartridge space
+            0x4020..=0xffff => self
+                .mapper
+                .borrow_mut()
+                .cpu_read(addr)
+                .unwrap_or(self.open_bus),
+            _ => self.open_bus,
+        };
+        self.open_bus = val;
+        val
+    }
+
+    // Read without side effects, for debuggers and tracing.
+    // I/O registers read back as $FF, like nestest.log shows them
+    pub fn peek8(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1fff => self.ram[(addr & 0x7ff) as usize],
+            0x4020..=0xffff => self.mapper.borrow().cpu_peek(addr).unwrap_or(0),
+            _ => 0xff,
         }
     }
 
-    pub fn read16(&self, addr: u16) -> u16 {
-        (self.read8(addr) as u16) | ((self.read8(addr + 1) as u16) << 8)
+    pub fn read16(&mut self, addr: u16) -> u16 {
+        (self.read8(addr) as u16) | ((self.read8(addr.wrapping_add(1)) as u16) << 8)
     }
 
+    // CPU write, takes one cycle
     pub fn write8(&mut self, addr: u16, val: u8) {
-        let mut ppu = self.ppu.borrow_mut();
+        self.tick();
+        self.open_bus = val;
         match addr {
-            // PPU Controller register
-            0x2000 => ppu.write_ctrl(val),
-            // PPU Mask register
-            0x2001 => ppu.write_mask(val),
-            // PPU OAM address
-            0x2003 => ppu.write_oam_addr(val),
-            // PPU OAM data
-            0x2004 => ppu.write_oam_data(val),
-            // PPU Scroll register
-            0x2005 => ppu.write_scroll(val),
-            // PPU Address register
-            0x2006 => ppu.write_address(val),
-            // PPU Data register
-            0x2007 => ppu.write_vram(val),
+            // 2 KB internam RAM mirrors
+            0x0000..=0x1fff => self.ram[(addr & 0x7ff) as usize] = val,
+            // PPU registers, mirrored every 8 bytes
+            0x2000..=0x3fff => {
+                self.mapper
+                    .borrow_mut()
+                    .ppu_register_write(addr & 0x2007, val);
+                let mut ppu = self.ppu.borrow_mut();
+                match addr & 0x2007 {
+                    // PPU Controller register
+                    0x2000 => ppu.write_ctrl(val),
+                    // PPU Mask register
+                    0x2001 => ppu.write_mask(val),
+                    // PPU OAM address
+                    0x2003 => ppu.write_oam_addr(val),
+                    // PPU OAM data
+                    0x2004 => ppu.write_oam_data(val),
+                    // PPU Scroll register
+                    0x2005 => ppu.write_scroll(val),
+                    // PPU Address register
+                    0x2006 => ppu.write_address(val),
+                    // PPU Data register
+                    0x2007 => ppu.write_vram(val),
+                    _ => {}
+                }
+            }
+            // APU registers
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write(addr, val),
+            // OAM DMA
+            0x4014 => self.oam_dma(val),
+            // Controller strobe, both ports
+            0x4016 => {
+                for controller in &mut self.controllers {
+                    controller.write(val);
+                }
+            }
+            // Cartridge space
+            0x4020..=0xffff => self.mapper.borrow_mut().cpu_write(addr, val),
             _ => {}
         }
     }
+
+    // Copy a CPU page into OAM through $2004. The CPU halts for a cycle, one
+    // more if needed to line up with a read cycle, then 256 read/write pairs:
+    // 513 or 514 cycles in total
+    fn oam_dma(&mut self, page: u8) {
+        self.tick();
+        if self.cycles % 2 == 1 {
+            self.tick();
+        }
+
+        let base = (page as u16) << 8;
+        for i in 0..OAM_DMA_LEN {
+            let val = self.read8(base | i);
+            self.tick();
+            self.ppu.get_mut().write_oam_data(val);
+        }
+    }
+
+    // Run the rest of the system for one CPU cycle, 3 PPU dots
+    fn tick(&mut self) {
+        // The CPU polls interrupts before an instruction's last cycle, so it
+        // looks at the lines as they were at the end of the previous one
+        self.nmi_prev = self.nmi;
+        self.irq_prev = self.irq();
+        self.cycles += 1;
+        self.mapper.borrow_mut().cpu_clock();
+
+        let ppu = self.ppu.get_mut();
+        for _ in 0..3 {
+            ppu.tick();
+        }
+        self.nmi |= ppu.poll_nmi();
+
+        let expansion = self.mapper.borrow().audio_output();
+        self.apu.tick(expansion);
+        if let Some(addr) = self.apu.dmc_request() {
+            self.dmc_dma(addr);
+        }
+    }
+
+    // Fetch a DMC sample byte. The CPU is halted for 4 cycles, the last one
+    // doing the read. Real hardware waits out write cycles and shares
+    // cycles with OAM DMA, that isn't modeled
+    fn dmc_dma(&mut self, addr: u16) {
+        for _ in 0..3 {
+            self.tick();
+        }
+        let val = self.read8(addr);
+        self.apu.dmc_fill(val);
+    }
+
+    // Buttons held on a controller port, 0 or 1
+    pub fn set_buttons(&mut self, port: usize, buttons: Buttons) {
+        self.controllers[port].set_buttons(buttons);
+    }
+
+    // Audio sample rate for audio_samples
+    pub fn set_sample_rate(&mut self, rate: f64) {
+        self.apu.set_sample_rate(rate);
+    }
+
+    // Nudge the audio sample rate by a ratio, for rate control
+    pub fn adjust_audio_rate(&mut self, ratio: f64) {
+        self.apu.adjust_rate(ratio);
+    }
+
+    // Append the audio produced since the last call
+    pub fn audio_samples(&mut self, out: &mut Vec<i16>) {
+        self.apu.samples(out);
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // PPU scanline and dot
+    pub fn ppu_position(&self) -> (u16, u16) {
+        self.ppu.borrow().position()
+    }
+
+    // Pixels of the last completed frame
+    pub fn frame(&self) -> Ref<'_, [Pixel]> {
+        Ref::map(self.ppu.borrow(), |ppu| ppu.frame())
+    }
+
+    // Whether the PPU finished a frame since the last poll
+    pub fn poll_frame(&mut self) -> bool {
+        self.ppu.get_mut().poll_frame()
+    }
+
+    // Take the latched NMI edge
+    pub fn poll_nmi(&mut self) -> bool {
+        self.nmi_prev = false;
+        std::mem::take(&mut self.nmi)
+    }
+
+    // NMI seen in time for the instruction that just finished
+    pub fn nmi_pending(&self) -> bool {
+        self.nmi_prev
+    }
+
+    // IRQ line seen in time for the instruction that just finished
+    pub fn irq_pending(&self) -> bool {
+        self.irq_prev
+    }
+
+    // Level triggered IRQ line, any source holds it low
+    pub fn irq(&self) -> bool {
+        self.mapper.borrow().irq() || self.apu.irq()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{test_image, Cartridge};
+    use crate::mapper;
+
+    fn memory() -> Memory {
+        Memory::new(mapper::new(Cartridge::new(&test_image(&[])).unwrap()).unwrap())
+    }
+
+    // Cycles taken by a $4014 write, the write's own cycle included
+    fn dma_cycles(mem: &mut Memory) -> u64 {
+        let start = mem.cycles();
+        mem.write8(0x4014, 0x02);
+        mem.cycles() - start
+    }
+
+    #[test]
+    fn oam_dma_cycles() {
+        let mut mem = memory();
+        // The write cycle, a halt cycle, 512 copy cycles and one more to
+        // line up with a read cycle when needed
+        if mem.cycles() % 2 == 1 {
+            mem.read8(0);
+        }
+        assert_eq!(dma_cycles(&mut mem), 514);
+        mem.read8(0);
+        assert_eq!(dma_cycles(&mut mem), 515);
+    }
+
+    #[test]
+    fn oam_dma_copies_from_oam_addr() {
+        let mut mem = memory();
+        for i in 0..=0xff {
+            mem.write8(0x0200 | i, i as u8);
+        }
+        // The copy starts at OAMADDR and wraps around
+        mem.write8(0x2003, 0x04);
+        mem.write8(0x4014, 0x02);
+        let mut oam = Vec::new();
+        for i in 0..=0xff {
+            mem.write8(0x2003, i);
+            oam.push(mem.read8(0x2004));
+        }
+        assert_eq!(oam[4], 0x00);
+        assert_eq!(oam[0], 0xfc);
+        // Attribute bytes lose bits 2-4
+        assert_eq!(oam[6], 0x02);
+        assert_eq!(oam[0x86], 0x82 & 0xe3);
+        assert_eq!(oam[0x87], 0x83);
+    }
 }
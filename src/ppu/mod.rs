@@ -1,16 +1,39 @@
+pub mod palette;
 mod regs;
+mod render;
+mod sprite;
 
 use self::Mirroring::*;
+use crate::mapper::MapperRef;
+use palette::Pixel;
 use regs::*;
+use render::Background;
+use sprite::Sprites;
 
 const OAM_SIZE: usize = 64 * 4;
-const VRAM_SIZE: usize = 2048;
+/// 2 KB of CIRAM plus 2 KB on four-screen boards
+const VRAM_SIZE: usize = 0x1000;
+const DOTS_PER_SCANLINE: u16 = 341;
+const SCANLINES_PER_FRAME: u16 = 262;
+/// Frames until an I/O latch bit that isn't refreshed decays to 0, about
+/// 600 ms
+const LATCH_DECAY_FRAMES: u32 = 36;
 
-#[derive(Debug, Clone, Copy)]
+/// Frame buffer dimensions
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mirroring {
     Vertical,
     Horizontal,
     FourScreen,
+    /// All four nametables show the first 1 KB of CIRAM
+    SingleScreenLower,
+    /// All four nametables show the second 1 KB of CIRAM
+    SingleScreenUpper,
+    /// Each nametable picks its own 1 KB CIRAM page
+    PerNametable([u8; 4]),
 }
 
 pub struct Ppu {
@@ -20,93 +43,169 @@ pub struct Ppu {
     mask: Mask,
     /// Status register
     stat: Status,
-    /// Scroll register
-    scroll: Scroll,
-    /// Address register
-    addr: Addr,
+    /// Current VRAM address
+    v: VramAddr,
+    /// Temporary VRAM address, the top left corner of the screen
+    t: VramAddr,
+    /// Fine X scroll, 3 bits
+    x: u8,
+    /// First or second write toggle, shared by $2005 and $2006
+    w: bool,
     /// OAM address, $2003
-    oam_addr: u16,
+    oam_addr: u8,
     /// OAM data, $2004
     oam_data: [u8; OAM_SIZE],
     /// Ppu's ram, $2007
     vram: [u8; VRAM_SIZE],
-    /// Character rom
-    char_rom: Vec<u8>,
+    /// Cartridge mapper, owns the pattern tables and mirroring
+    mapper: MapperRef,
     /// Frame palette
     palette_ram: [u8; 32],
-    /// Nametable mirroring
-    mirroring: Mirroring,
     /// NMI Interrupt flag
     nmi: bool,
     /// Internal data buf
     data_buf: u8,
+    /// Last value driven on the CPU data bus, read back from write-only
+    /// registers
+    io_latch: u8,
+    /// Frame each latch bit was last refreshed
+    latch_refresh: [u32; 8],
+    /// Frames since power-on
+    frame_count: u32,
+    /// Current scanline, 0-239 visible, 241-260 vblank, 261 pre-render
+    scanline: u16,
+    /// Current dot within the scanline, 0-340
+    dot: u16,
+    /// Odd frames are one dot shorter while rendering
+    odd_frame: bool,
+    /// Background fetch state
+    bg: Background,
+    /// Sprite evaluation and fetch state
+    sprites: Sprites,
+    /// Pixels of the frame being drawn, WIDTH x HEIGHT
+    frame: Vec<Pixel>,
+    /// A frame was completed since the last poll
+    frame_ready: bool,
+}
+
+// $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C, the
+// whole $3F00-$3FFF range repeats every 32 bytes
+fn palette_index(addr: u16) -> usize {
+    let index = addr & 0x1f;
+    if index & 0x13 == 0x10 {
+        (index & 0x0f) as usize
+    } else {
+        index as usize
+    }
 }
 
 impl Ppu {
-    pub fn new(char_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+    pub fn new(mapper: MapperRef) -> Self {
         Self {
             ctrl: Control::empty(),
             mask: Mask::empty(),
             stat: Status::empty(),
-            scroll: Scroll::default(),
-            addr: Addr::new(),
+            v: VramAddr::default(),
+            t: VramAddr::default(),
+            x: 0,
+            w: false,
             oam_addr: 0,
             oam_data: [0u8; OAM_SIZE],
             vram: [0u8; VRAM_SIZE],
-            char_rom,
+            mapper,
             palette_ram: [0u8; 32],
-            mirroring,
             nmi: false,
             data_buf: 0,
+            io_latch: 0,
+            latch_refresh: [0; 8],
+            frame_count: 0,
+            scanline: 0,
+            dot: 0,
+            odd_frame: false,
+            bg: Background::default(),
+            sprites: Sprites::default(),
+            frame: vec![0; WIDTH * HEIGHT],
+            frame_ready: false,
         }
     }
 
-    // Read the status register
+    // Scanline and dot about to be rendered
+    pub fn position(&self) -> (u16, u16) {
+        (self.scanline, self.dot)
+    }
+
+    // Pixels of the last completed frame, row major
+    pub fn frame(&self) -> &[Pixel] {
+        &self.frame
+    }
+
+    // Take the frame completed flag, set at the start of vblank
+    pub fn poll_frame(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    // Take the pending NMI, if any
+    pub fn poll_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi)
+    }
+
+    // Read a write-only register, the I/O latch is all that's left on the bus
+    pub fn read_latch(&self) -> u8 {
+        self.io_latch
+    }
+
+    // Read the status register. The low 5 bits aren't driven and come from
+    // the I/O latch
     pub fn read_stat(&mut self) -> u8 {
-        let bits = self.stat.bits();
+        let bits = self.stat.bits() & 0xe0;
         self.stat.remove(Status::V);
-        self.scroll.latched = false;
-        self.addr.hi = true;
-        bits
+        self.w = false;
+        self.refresh_latch(bits, 0xe0)
     }
 
     // Read a byte from the OAM
-    pub fn read_oam(&self) -> u8 {
-        self.oam_data[self.oam_addr as usize]
+    pub fn read_oam(&mut self) -> u8 {
+        let val = self.oam_data[self.oam_addr as usize];
+        self.refresh_latch(val, 0xff)
     }
 
     // Read from vram
     pub fn read_vram(&mut self) -> u8 {
-        let addr = self.addr.raw;
-        self.addr.increment(self.ctrl.increment_amt());
+        let addr = self.v.addr();
+        self.mapper.borrow_mut().ppu_address(addr);
+        self.increment_vram();
         match addr {
             // All reads in range 0 - $3eff will return the contents of an internal read buffer
             // this read buffer is updated after the read operation with the current vram address
 
-            // Character rom/pattern tables
+            // Pattern tables
             0x0000..=0x1fff => {
                 let res = self.data_buf;
-                self.data_buf = self.char_rom[addr as usize];
-                res
+                self.data_buf = self.mapper.borrow_mut().ppu_read(addr);
+                self.refresh_latch(res, 0xff)
             }
-            // Internal vram/nametables
-            0x2000..=0x2fff => {
+            // Internal vram/nametables, mirrored at $3000-$3EFF
+            0x2000..=0x3eff => {
                 let res = self.data_buf;
-                self.data_buf = self.vram[self.mirror(addr) as usize];
-                res
+                self.data_buf = self.nametable(addr);
+                self.refresh_latch(res, 0xff)
+            }
+            // Palette reads skip the buffer and only drive the low 6 bits.
+            // The buffer still gets the nametable byte underneath
+            _ => {
+                self.data_buf = self.nametable(addr);
+                let val = self.palette_ram[palette_index(addr)] & self.mask.greyscale();
+                self.refresh_latch(val, 0x3f)
             }
-            // Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => self.palette_ram[((addr - 0x10) & 0xff) as usize],
-            // Palette RAM indexes
-            0x3f00..=0x3f1f => self.palette_ram[(addr & 0xff) as usize],
-            _ => 0,
         }
     }
 
     // Write to the control register
     pub fn write_ctrl(&mut self, val: u8) {
+        self.refresh_latch(val, 0xff);
         let nmi = self.ctrl.nmi();
         self.ctrl.update(val);
+        self.t.set_nametable(val);
         if !nmi && self.ctrl.nmi() && self.stat.in_vblank() {
             self.nmi = true;
         }
@@ -114,61 +213,313 @@ impl Ppu {
 
     // Write to the mask register
     pub fn write_mask(&mut self, val: u8) {
+        self.refresh_latch(val, 0xff);
         self.mask.update(val);
     }
 
     // Write to the oam address
     pub fn write_oam_addr(&mut self, val: u8) {
-        self.oam_addr = val as u16;
+        self.refresh_latch(val, 0xff);
+        self.oam_addr = val;
     }
 
     // Write oam data
     pub fn write_oam_data(&mut self, val: u8) {
+        self.refresh_latch(val, 0xff);
+        // Bits 2-4 of the attribute byte don't exist
+        let val = if self.oam_addr & 0x03 == 2 {
+            val & 0xe3
+        } else {
+            val
+        };
         self.oam_data[self.oam_addr as usize] = val;
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
     // Write to the scroll register
     pub fn write_scroll(&mut self, val: u8) {
-        self.scroll.update(val);
+        self.refresh_latch(val, 0xff);
+        if self.w {
+            self.t.set_coarse_y(val >> 3);
+            self.t.set_fine_y(val);
+        } else {
+            self.t.set_coarse_x(val >> 3);
+            self.x = val & 0x07;
+        }
+        self.w = !self.w;
     }
 
     // Write to the address register
     pub fn write_address(&mut self, val: u8) {
-        self.addr.update(val);
+        self.refresh_latch(val, 0xff);
+        if self.w {
+            self.t.set_low(val);
+            self.v = self.t;
+            self.mapper.borrow_mut().ppu_address(self.v.addr());
+        } else {
+            self.t.set_high(val);
+        }
+        self.w = !self.w;
     }
 
     // Write to the data register
     pub fn write_vram(&mut self, val: u8) {
-        let addr = self.addr.raw;
+        self.refresh_latch(val, 0xff);
+        let addr = self.v.addr();
+        self.mapper.borrow_mut().ppu_address(addr);
         match addr {
-            // Character rom/pattern tables
-            0x0000..=0x1fff => {
-                panic!("Attempted write to character rom: {}", addr);
+            // Pattern tables
+            0x0000..=0x1fff => self.mapper.borrow_mut().ppu_write(addr, val),
+            // Internal vram/nametables, mirrored at $3000-$3EFF
+            0x2000..=0x3eff => {
+                if !self.mapper.borrow_mut().nametable_write(addr & 0x2fff, val) {
+                    self.vram[self.mirror(addr) as usize] = val;
+                }
             }
-            // Internal vram/nametables
-            0x2000..=0x2fff => {
-                self.vram[self.mirror(addr) as usize] = val;
+            // Palette RAM, 6 bits wide
+            _ => self.palette_ram[palette_index(addr)] = val & 0x3f,
+        }
+        self.increment_vram();
+    }
+
+    // Drive the masked bits of the I/O latch and return the whole latch,
+    // which is what the CPU sees
+    fn refresh_latch(&mut self, val: u8, mask: u8) -> u8 {
+        self.io_latch = (self.io_latch & !mask) | (val & mask);
+        for bit in 0..8 {
+            if mask & (1 << bit) != 0 {
+                self.latch_refresh[bit] = self.frame_count;
             }
-            // Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                self.palette_ram[((addr - 0x10) & 0xff) as usize] = val
+        }
+        self.io_latch
+    }
+
+    // Once a frame, clear latch bits that haven't been driven in a while
+    fn decay_latch(&mut self) {
+        self.frame_count += 1;
+        for bit in 0..8 {
+            if self.frame_count - self.latch_refresh[bit] >= LATCH_DECAY_FRAMES {
+                self.io_latch &= !(1 << bit);
             }
-            // Palette RAM indexes
-            0x3f00..=0x3f1f => self.palette_ram[(addr & 0xff) as usize] = val,
-            _ => {}
         }
-        self.addr.increment(self.ctrl.increment_amt());
+    }
+
+    // Step v after a $2007 access. While rendering the PPU is already
+    // walking v, and the access bumps coarse X and Y at the same time
+    fn increment_vram(&mut self) {
+        if self.rendering() {
+            self.v.increment_x();
+            self.v.increment_y();
+        } else {
+            self.v.increment(self.ctrl.increment_amt());
+        }
+    }
+
+    // Nametable byte for a $2007 read, from the mapper if it takes over the
+    // address. Rendering fetches go through read_nametable
+    fn nametable(&self, addr: u16) -> u8 {
+        let val = self.mapper.borrow().nametable_peek(addr & 0x2fff);
+        val.unwrap_or_else(|| self.vram[self.mirror(addr) as usize])
     }
 
     fn mirror(&self, addr: u16) -> u16 {
-        let addr = addr - 0x2000;
+        let addr = addr & 0x0fff;
         let nametable = addr / 0x400;
-        match (self.mirroring, nametable) {
+        match (self.mapper.borrow().mirroring(), nametable) {
             (Vertical, 2 | 3) => addr - 0x800,
             (Horizontal, 1 | 2) => addr - 0x400,
             (Horizontal, 3) => addr - 0x800,
+            (SingleScreenLower, _) => addr & 0x3ff,
+            (SingleScreenUpper, _) => 0x400 | (addr & 0x3ff),
+            (PerNametable(pages), n) => {
+                (u16::from(pages[n as usize] & 0x01) << 10) | (addr & 0x3ff)
+            }
             _ => addr,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{test_image, Cartridge};
+    use crate::mapper;
+
+    fn setup() -> Ppu {
+        let rom = test_image(&[]);
+        Ppu::new(mapper::new(Cartridge::new(&rom).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn scroll_and_address_writes() {
+        let mut ppu = setup();
+        ppu.write_ctrl(0x02);
+        assert_eq!(ppu.t.addr(), 0x0800);
+
+        // $2005 sets coarse and fine X, then coarse and fine Y
+        ppu.read_stat();
+        ppu.write_scroll(0x7d);
+        assert_eq!((ppu.t.coarse_x(), ppu.x, ppu.w), (0x0f, 5, true));
+        ppu.write_scroll(0x5e);
+        assert_eq!((ppu.t.coarse_y(), ppu.t.fine_y(), ppu.w), (0x0b, 6, false));
+        assert_eq!(ppu.t.addr(), 0x296f);
+
+        // $2006 replaces the high bits and clears fine Y bit 2, the second
+        // write copies t to v
+        ppu.write_address(0x3d);
+        assert_eq!((ppu.t.fine_y(), ppu.w), (3, true));
+        ppu.write_address(0xf0);
+        assert_eq!(ppu.t.addr(), 0x3df0);
+        assert_eq!(ppu.v.addr(), 0x3df0);
+        assert_eq!(ppu.x, 5);
+    }
+
+    #[test]
+    fn status_read_resets_toggle() {
+        let mut ppu = setup();
+        ppu.write_address(0x21);
+        ppu.read_stat();
+        ppu.write_address(0x23);
+        ppu.write_address(0x45);
+        assert_eq!(ppu.v.addr(), 0x2345);
+    }
+
+    #[test]
+    fn data_access_increments() {
+        let mut ppu = setup();
+        ppu.write_address(0x20);
+        ppu.write_address(0x00);
+        ppu.write_vram(0);
+        assert_eq!(ppu.v.addr(), 0x2001);
+        ppu.write_ctrl(0x04);
+        ppu.read_vram();
+        assert_eq!(ppu.v.addr(), 0x2021);
+    }
+
+    fn write(ppu: &mut Ppu, addr: u16, val: u8) {
+        ppu.write_address((addr >> 8) as u8);
+        ppu.write_address(addr as u8);
+        ppu.write_vram(val);
+    }
+
+    fn read(ppu: &mut Ppu, addr: u16) -> u8 {
+        ppu.write_address((addr >> 8) as u8);
+        ppu.write_address(addr as u8);
+        ppu.read_vram();
+        ppu.read_vram()
+    }
+
+    const NAMETABLES: [u16; 4] = [0x2000, 0x2400, 0x2800, 0x2c00];
+
+    // Clear the nametables and write to one, then read all four
+    fn mirrored(ppu: &mut Ppu, addr: u16) -> [u8; 4] {
+        for nametable in NAMETABLES {
+            write(ppu, nametable, 0);
+        }
+        write(ppu, addr, 0x55);
+        NAMETABLES.map(|nametable| read(ppu, nametable))
+    }
+
+    #[test]
+    fn mirroring() {
+        let mut rom = test_image(&[]);
+        let mut ppu = Ppu::new(mapper::new(Cartridge::new(&rom).unwrap()).unwrap());
+        assert_eq!(mirrored(&mut ppu, 0x2400), [0x55, 0x55, 0, 0]);
+
+        rom[6] = 0x01;
+        let mut ppu = Ppu::new(mapper::new(Cartridge::new(&rom).unwrap()).unwrap());
+        assert_eq!(mirrored(&mut ppu, 0x2800), [0x55, 0, 0x55, 0]);
+        // $3000-$3EFF mirrors $2000-$2EFF
+        assert_eq!(mirrored(&mut ppu, 0x3400), [0, 0x55, 0, 0x55]);
+    }
+
+    #[test]
+    fn single_screen() {
+        // AxROM picks the CIRAM page with bit 4 of its register
+        let mut rom = test_image(&[]);
+        rom[6] = 0x70;
+        let mapper = mapper::new(Cartridge::new(&rom).unwrap()).unwrap();
+        let mut ppu = Ppu::new(mapper.clone());
+        assert_eq!(mirrored(&mut ppu, 0x2c00), [0x55; 4]);
+        mapper.borrow_mut().cpu_write(0x8000, 0x10);
+        write(&mut ppu, 0x2000, 0xaa);
+        assert_eq!(
+            NAMETABLES.map(|nametable| read(&mut ppu, nametable)),
+            [0xaa; 4]
+        );
+        mapper.borrow_mut().cpu_write(0x8000, 0x00);
+        assert_eq!(read(&mut ppu, 0x2000), 0x55);
+    }
+
+    #[test]
+    fn read_buffer() {
+        let mut ppu = setup();
+        write(&mut ppu, 0x2f00, 0x12);
+        write(&mut ppu, 0x2f01, 0x34);
+        ppu.write_address(0x2f);
+        ppu.write_address(0x00);
+        ppu.read_vram();
+        assert_eq!(ppu.read_vram(), 0x12);
+        assert_eq!(ppu.read_vram(), 0x34);
+
+        // Palette reads are immediate and leave the nametable byte
+        // underneath in the buffer
+        write(&mut ppu, 0x3f01, 0x2a);
+        ppu.write_address(0x3f);
+        ppu.write_address(0x01);
+        assert_eq!(ppu.read_vram() & 0x3f, 0x2a);
+        ppu.write_address(0x20);
+        ppu.write_address(0x00);
+        assert_eq!(ppu.read_vram(), 0x34);
+    }
+
+    #[test]
+    fn palette_mirrors() {
+        let mut ppu = setup();
+        write(&mut ppu, 0x3f10, 0x21);
+        write(&mut ppu, 0x3f14, 0x22);
+        write(&mut ppu, 0x3f11, 0x23);
+        assert_eq!(ppu.palette_ram[0x00], 0x21);
+        assert_eq!(ppu.palette_ram[0x04], 0x22);
+        assert_eq!(ppu.palette_ram[0x11], 0x23);
+        assert_eq!(ppu.palette_ram[0x01], 0x00);
+        // The whole range repeats every 32 bytes, 6 bits wide
+        write(&mut ppu, 0x3fe2, 0xff);
+        assert_eq!(ppu.palette_ram[0x02], 0x3f);
+    }
+
+    #[test]
+    fn io_latch() {
+        let mut ppu = setup();
+        // Write-only registers read back the last value written
+        ppu.write_mask(0x9a);
+        assert_eq!(ppu.read_latch(), 0x9a);
+        // $2002 drives the top 3 bits, the low 5 come from the latch
+        ppu.stat.insert(Status::V);
+        assert_eq!(ppu.read_stat(), 0x9a);
+        assert_eq!(ppu.read_latch(), 0x9a);
+        assert_eq!(ppu.read_stat(), 0x1a);
+        // Palette reads leave the top 2 bits alone
+        write(&mut ppu, 0x3f00, 0x0f);
+        ppu.write_address(0x3f);
+        ppu.write_address(0x00);
+        ppu.write_ctrl(0xc0);
+        assert_eq!(ppu.read_vram(), 0xcf);
+    }
+
+    #[test]
+    fn io_latch_decay() {
+        let mut ppu = setup();
+        ppu.write_mask(0xff);
+        ppu.frame_count += LATCH_DECAY_FRAMES - 10;
+        // Refresh the low 5 bits
+        ppu.refresh_latch(0x1f, 0x1f);
+        ppu.frame_count += 9;
+        ppu.decay_latch();
+        assert_eq!(ppu.read_latch(), 0x1f);
+        for _ in 0..LATCH_DECAY_FRAMES {
+            ppu.decay_latch();
+        }
+        assert_eq!(ppu.read_latch(), 0x00);
+    }
+}
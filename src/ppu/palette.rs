@@ -0,0 +1,293 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+use std::{error, fmt};
+
+use crate::cartridge::Region;
+
+/// Colors per table, 64 palette indexes times 8 emphasis combinations
+pub const PALETTE_SIZE: usize = 64 * 8;
+/// .pal file with the 64 base colors
+const PAL_FILE_SIZE: usize = 64 * 3;
+/// .pal file with every emphasis combination
+const PAL_FILE_FULL_SIZE: usize = PALETTE_SIZE * 3;
+
+/// Level of the channels that aren't emphasized, measured on a 2C02
+const ATTENUATION: f32 = 0.816328;
+
+/// Composite signal levels relative to sync, low and high halves of the
+/// square wave for each luma level, as measured on a 2C02
+const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const SIGNAL_BLACK: f32 = 0.518;
+const SIGNAL_WHITE: f32 = 1.962;
+/// Emphasis attenuates the signal during its phases
+const SIGNAL_ATTENUATION: f32 = 0.746;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteError {
+    /// A .pal file has 192 or 1536 bytes
+    BadSize(usize),
+    /// NTSC settings aren't comma separated name=value pairs with known
+    /// names and numeric values
+    BadNtscParam(String),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadSize(len) => write!(
+                f,
+                "palette file is {len} bytes, expected {PAL_FILE_SIZE} or {PAL_FILE_FULL_SIZE}"
+            ),
+            Self::BadNtscParam(param) => write!(
+                f,
+                "bad NTSC setting {param:?}, expected hue, sat, contrast, brightness or gamma=<number>"
+            ),
+        }
+    }
+}
+
+impl error::Error for PaletteError {}
+
+/// TV decoder settings for generated palettes
+#[derive(Debug, Clone, Copy)]
+pub struct NtscParams {
+    /// Hue rotation in degrees
+    pub hue: f32,
+    /// Chroma gain, 0 gives greyscale
+    pub saturation: f32,
+    /// Luma gain
+    pub contrast: f32,
+    /// Luma offset
+    pub brightness: f32,
+    /// Display gamma, 1.0 leaves the decoded levels as is
+    pub gamma: f32,
+}
+
+impl Default for NtscParams {
+    fn default() -> Self {
+        Self {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+// Settings written as "hue=10,sat=1.2", unnamed settings keep their defaults
+impl FromStr for NtscParams {
+    type Err = PaletteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut params = Self::default();
+        for param in s.split(',').filter(|p| !p.is_empty()) {
+            let bad = || PaletteError::BadNtscParam(param.to_string());
+            let (name, val) = param.split_once('=').ok_or_else(bad)?;
+            let val: f32 = val.trim().parse().map_err(|_| bad())?;
+            match name.trim() {
+                "hue" => params.hue = val,
+                "sat" | "saturation" => params.saturation = val,
+                "contrast" => params.contrast = val,
+                "brightness" => params.brightness = val,
+                "gamma" => params.gamma = val,
+                _ => return Err(bad()),
+            }
+        }
+        Ok(params)
+    }
+}
+
+/// Frame buffer pixel layout: palette index in bits 0-5, emphasis bits from
+/// $2001 in bits 6-8
+pub type Pixel = u16;
+
+// Converts frame buffer pixels to RGB
+pub struct Palette {
+    colors: Vec<(u8, u8, u8)>,
+}
+
+impl Palette {
+    // Expand 64 base colors into a full table with every emphasis
+    // combination. PAL and Dendy PPUs swap the red and green bits
+    pub fn new(base: &[(u8, u8, u8); 64], region: Region) -> Self {
+        let swap = matches!(region, Region::Pal | Region::Dendy);
+        let mut colors = Vec::with_capacity(PALETTE_SIZE);
+
+        for emphasis in 0..8 {
+            let (red, green) = (emphasis & 1 != 0, emphasis & 2 != 0);
+            let (red, green) = if swap { (green, red) } else { (red, green) };
+            let blue = emphasis & 4 != 0;
+
+            for (index, &(r, g, b)) in base.iter().enumerate() {
+                // The black columns aren't affected
+                if emphasis == 0 || index & 0x0e == 0x0e {
+                    colors.push((r, g, b));
+                    continue;
+                }
+                // With all three bits set every channel is darkened
+                let dim = |val: u8, emphasized: bool| {
+                    if emphasized && emphasis != 7 {
+                        val
+                    } else {
+                        (f32::from(val) * ATTENUATION).round() as u8
+                    }
+                };
+                colors.push((dim(r, red), dim(g, green), dim(b, blue)));
+            }
+        }
+
+        Self { colors }
+    }
+
+    // Load a .pal file, either the 64 base colors or a full table
+    pub fn from_pal(data: &[u8], region: Region) -> Result<Self, PaletteError> {
+        let colors: Vec<_> = data.chunks_exact(3).map(|c| (c[0], c[1], c[2])).collect();
+        match data.len() {
+            PAL_FILE_SIZE => {
+                let mut base = [(0, 0, 0); 64];
+                base.copy_from_slice(&colors);
+                Ok(Self::new(&base, region))
+            }
+            PAL_FILE_FULL_SIZE => Ok(Self { colors }),
+            len => Err(PaletteError::BadSize(len)),
+        }
+    }
+
+    // Decode the PPU's composite output for every pixel value. Emphasis
+    // is applied to the signal, so it comes out like on a real TV
+    pub fn ntsc(params: &NtscParams, region: Region) -> Self {
+        let swap = matches!(region, Region::Pal | Region::Dendy);
+        let colors = (0..PALETTE_SIZE as u16)
+            .map(|pixel| {
+                let pixel = if swap {
+                    (pixel & 0x13f) | ((pixel & 0x40) << 1) | ((pixel & 0x80) >> 1)
+                } else {
+                    pixel
+                };
+                decode(pixel, params)
+            })
+            .collect();
+        Self { colors }
+    }
+
+    // RGB for a frame buffer pixel
+    pub fn rgb(&self, pixel: Pixel) -> (u8, u8, u8) {
+        self.colors[pixel as usize % PALETTE_SIZE]
+    }
+
+    // All 512 colors, indexed by pixel value
+    pub fn colors(&self) -> &[(u8, u8, u8)] {
+        &self.colors
+    }
+}
+
+// Signal level of a pixel at one of the 12 phases of the color subcarrier
+fn signal(pixel: u16, phase: u16) -> f32 {
+    let color = pixel & 0x0f;
+    // Columns $E and $F are black at any level
+    let level = if color > 0x0d { 1 } else { (pixel >> 4) & 0x03 } as usize;
+    let emphasis = pixel >> 6;
+
+    // Color 0 stays high, $D-$F stay low, the rest is a square wave
+    // with its phase picked by the color
+    let (low, high) = (SIGNAL_LOW[level], SIGNAL_HIGH[level]);
+    let (low, high) = match color {
+        0x00 => (high, high),
+        0x0d..=0x0f => (low, low),
+        _ => (low, high),
+    };
+    let in_phase = |color: u16| (color + phase) % 12 < 6;
+    let signal = if in_phase(color) { high } else { low };
+
+    if (emphasis & 1 != 0 && in_phase(0))
+        || (emphasis & 2 != 0 && in_phase(4))
+        || (emphasis & 4 != 0 && in_phase(8))
+    {
+        signal * SIGNAL_ATTENUATION
+    } else {
+        signal
+    }
+}
+
+// Demodulate one subcarrier cycle of a pixel into RGB
+fn decode(pixel: u16, params: &NtscParams) -> (u8, u8, u8) {
+    let (mut y, mut u, mut v) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let level = (signal(pixel, phase) - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK);
+        // Color $8 lines up with the burst at 180 degrees
+        let angle = (0.5 - f32::from(phase)) * PI / 6.0 + params.hue.to_radians();
+        y += level;
+        u += level * angle.cos();
+        v += level * angle.sin();
+    }
+
+    let y = y / 12.0 * params.contrast + params.brightness;
+    let (u, v) = (u / 6.0 * params.saturation, v / 6.0 * params.saturation);
+
+    let channel = |val: f32| (val.clamp(0.0, 1.0).powf(1.0 / params.gamma) * 255.0).round() as u8;
+    (
+        channel(y + 1.140 * v),
+        channel(y - 0.395 * u - 0.581 * v),
+        channel(y + 2.032 * u),
+    )
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::new(&SYSTEM_PALETTE, Region::Ntsc)
+    }
+}
+
+#[rustfmt::skip]
+pub static SYSTEM_PALETTE: [(u8, u8, u8); 64] = [
+   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
+   (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
+   (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
+   (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+   (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
+   (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
+   (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
+   (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+   (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
+   (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
+   (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
+   (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emphasis() {
+        let base = [(200, 200, 200); 64];
+        let palette = Palette::new(&base, Region::Ntsc);
+        let dim = (200.0 * ATTENUATION).round() as u8;
+        // Red emphasis dims green and blue, all three dim everything
+        assert_eq!(palette.rgb(0x40), (200, dim, dim));
+        assert_eq!(palette.rgb(0x1c0), (dim, dim, dim));
+        // Black columns are left alone
+        assert_eq!(palette.rgb(0x1c0 | 0x0e), (200, 200, 200));
+        // PAL swaps red and green
+        let palette = Palette::new(&base, Region::Pal);
+        assert_eq!(palette.rgb(0x40), (dim, 200, dim));
+    }
+
+    #[test]
+    fn ntsc_params() {
+        let params: NtscParams = "hue=-5, sat=1.2,gamma=1.1".parse().unwrap();
+        assert_eq!(params.hue, -5.0);
+        assert_eq!(params.saturation, 1.2);
+        assert_eq!(params.contrast, 1.0);
+        assert_eq!(params.brightness, 0.0);
+        assert_eq!(params.gamma, 1.1);
+
+        assert!("".parse::<NtscParams>().is_ok());
+        assert!("tint=3".parse::<NtscParams>().is_err());
+        assert!("hue".parse::<NtscParams>().is_err());
+        assert!("hue=abc".parse::<NtscParams>().is_err());
+    }
+}
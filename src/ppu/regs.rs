@@ -35,24 +35,30 @@ impl Control {
         }
     }
 
-    pub fn nametable_address(&self) -> u16 {
-        match self.bits() & 0x3 {
-            0 => 0x2000,
-            1 => 0x2400,
-            2 => 0x2800,
-            3 => 0x2c00,
-            _ => unreachable!(),
+    pub fn background_patterntable_address(&self) -> usize {
+        if self.contains(Self::B) {
+            0x1000
+        } else {
+            0x0000
         }
     }
 
-    pub fn background_patterntable_address(&self) -> usize {
-        if self.contains(Self::B) {
+    pub fn sprite_patterntable_address(&self) -> u16 {
+        if self.contains(Self::S) {
             0x1000
         } else {
             0x0000
         }
     }
 
+    pub fn sprite_height(&self) -> u16 {
+        if self.contains(Self::H) {
+            16
+        } else {
+            8
+        }
+    }
+
     pub fn nmi(&self) -> bool {
         self.contains(Self::V)
     }
@@ -95,6 +101,43 @@ impl Mask {
             *self = Self::from_bits_unchecked(val);
         }
     }
+
+    /// Mask applied to palette indexes, greyscale keeps only the
+    /// grey column
+    pub fn greyscale(&self) -> u8 {
+        if self.contains(Self::GS) {
+            0x30
+        } else {
+            0x3f
+        }
+    }
+
+    /// Emphasis bits moved to bits 6-8 of a pixel
+    pub fn emphasis(&self) -> u16 {
+        u16::from(self.bits() & 0xe0) << 1
+    }
+
+    pub fn show_background(&self) -> bool {
+        self.contains(Self::RC3)
+    }
+
+    pub fn show_sprites(&self) -> bool {
+        self.contains(Self::RC4)
+    }
+
+    pub fn background_left(&self) -> bool {
+        self.contains(Self::RC1)
+    }
+
+    pub fn sprites_left(&self) -> bool {
+        self.contains(Self::RC2)
+    }
+
+    // Background or sprites enabled, the PPU fetches and updates its
+    // counters only while rendering
+    pub fn rendering(&self) -> bool {
+        self.intersects(Self::RC3 | Self::RC4)
+    }
 }
 
 // Read only Ppu Status register, mapped to $2002
@@ -123,53 +166,172 @@ impl Status {
     }
 }
 
-// Write only Ppu Scrolling position register, mapped to $2005
-#[derive(Default)]
-pub struct Scroll {
-    /// alternate writes
-    pub latched: bool,
-    /// scroll-x
-    x: u8,
-    /// scroll-y
-    y: u8,
-}
+const ADDR_MASK: u16 = 0x7fff;
 
-impl Scroll {
-    pub fn update(&mut self, val: u8) {
-        if self.latched {
-            self.y = val;
+// Internal VRAM address, used for both the current address v and the
+// temporary address t. $2005 and $2006 write t, and rendering walks v
+// across the nametables:
+// yyy NN YYYYY XXXXX
+// ||| || ||||| +++++-- coarse X scroll
+// ||| || +++++-------- coarse Y scroll
+// ||| ++-------------- nametable select
+// +++----------------- fine Y scroll
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VramAddr(u16);
+
+impl VramAddr {
+    /// PPU bus address, the fine Y top bit is dropped
+    pub fn addr(&self) -> u16 {
+        self.0 & 0x3fff
+    }
+
+    pub fn coarse_x(&self) -> u16 {
+        self.0 & 0x001f
+    }
+
+    pub fn coarse_y(&self) -> u16 {
+        (self.0 >> 5) & 0x001f
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        (self.0 >> 12) & 0x0007
+    }
+
+    pub fn set_coarse_x(&mut self, val: u8) {
+        self.0 = (self.0 & !0x001f) | (u16::from(val) & 0x1f);
+    }
+
+    pub fn set_coarse_y(&mut self, val: u8) {
+        self.0 = (self.0 & !0x03e0) | ((u16::from(val) & 0x1f) << 5);
+    }
+
+    pub fn set_nametable(&mut self, val: u8) {
+        self.0 = (self.0 & !0x0c00) | ((u16::from(val) & 0x03) << 10);
+    }
+
+    pub fn set_fine_y(&mut self, val: u8) {
+        self.0 = (self.0 & !0x7000) | ((u16::from(val) & 0x07) << 12);
+    }
+
+    /// First $2006 write, bits 8-13. Bit 14 is cleared
+    pub fn set_high(&mut self, val: u8) {
+        self.0 = (self.0 & 0x00ff) | ((u16::from(val) & 0x3f) << 8);
+    }
+
+    /// Second $2006 write, bits 0-7
+    pub fn set_low(&mut self, val: u8) {
+        self.0 = (self.0 & 0xff00) | u16::from(val);
+    }
+
+    pub fn increment(&mut self, amt: u16) {
+        self.0 = self.0.wrapping_add(amt) & ADDR_MASK;
+    }
+
+    /// Next tile to the right, wrapping into the horizontal nametable
+    pub fn increment_x(&mut self) {
+        if self.coarse_x() == 31 {
+            self.0 &= !0x001f;
+            self.0 ^= 0x0400;
         } else {
-            self.x = val;
+            self.0 += 1;
         }
-        self.latched = !self.latched;
     }
-}
 
-const ADDR_MAX: u16 = 0x3fff;
+    /// Next pixel row down. Coarse Y wraps at 30 into the vertical
+    /// nametable, or at 31 without switching when set out of range
+    pub fn increment_y(&mut self) {
+        if self.fine_y() < 7 {
+            self.0 += 0x1000;
+            return;
+        }
+        self.0 &= !0x7000;
+        match self.coarse_y() {
+            29 => {
+                self.set_coarse_y(0);
+                self.0 ^= 0x0800;
+            }
+            31 => self.set_coarse_y(0),
+            y => self.set_coarse_y(y as u8 + 1),
+        }
+    }
+
+    /// Coarse X and the horizontal nametable bit from t
+    pub fn copy_x(&mut self, t: VramAddr) {
+        self.0 = (self.0 & !0x041f) | (t.0 & 0x041f);
+    }
+
+    /// Fine Y, coarse Y and the vertical nametable bit from t
+    pub fn copy_y(&mut self, t: VramAddr) {
+        self.0 = (self.0 & !0x7be0) | (t.0 & 0x7be0);
+    }
+
+    /// Nametable byte of the tile at this address
+    pub fn tile_addr(&self) -> u16 {
+        0x2000 | (self.0 & 0x0fff)
+    }
 
-// Write only Ppu Address register, mapped to $2006
-pub struct Addr {
-    /// Alternate between hi and lo
-    pub hi: bool,
-    /// Raw addr
-    pub raw: u16,
+    /// Attribute byte covering the tile at this address
+    pub fn attribute_addr(&self) -> u16 {
+        0x23c0 | (self.0 & 0x0c00) | ((self.coarse_y() >> 2) << 3) | (self.coarse_x() >> 2)
+    }
 }
 
-impl Addr {
-    pub fn new() -> Self {
-        Self { raw: 0, hi: true }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increment_x_wraps_into_next_nametable() {
+        let mut v = VramAddr(0x001e);
+        v.increment_x();
+        assert_eq!(v.0, 0x001f);
+        v.increment_x();
+        assert_eq!(v.0, 0x0400);
+        v.0 = 0x041f;
+        v.increment_x();
+        assert_eq!(v.0, 0x0000);
     }
 
-    pub fn update(&mut self, val: u8) {
-        self.raw = if self.hi {
-            (self.raw & 0x00ff) | ((val as u16) << 8)
-        } else {
-            (self.raw & 0xff00) | (val as u16)
-        } & ADDR_MAX;
-        self.hi = !self.hi
+    #[test]
+    fn increment_y() {
+        // Fine Y first, then coarse Y
+        let mut v = VramAddr(0x6020);
+        v.increment_y();
+        assert_eq!(v.0, 0x7020);
+        v.increment_y();
+        assert_eq!(v.0, 0x0040);
+
+        // Row 29 wraps into the next nametable down
+        let mut v = VramAddr(0x73a5);
+        v.increment_y();
+        assert_eq!(v.0, 0x0805);
+
+        // Rows 30 and 31 are attribute data, 31 wraps without switching
+        let mut v = VramAddr(0x73c0);
+        v.increment_y();
+        assert_eq!(v.coarse_y(), 31);
+        v.0 |= 0x7000;
+        v.increment_y();
+        assert_eq!(v.0, 0x0000);
     }
 
-    pub fn increment(&mut self, amt: u16) {
-        self.raw = self.raw.wrapping_add(amt) & ADDR_MAX;
+    #[test]
+    fn copy_from_t() {
+        let t = VramAddr(0x7fff);
+        let mut v = VramAddr(0);
+        v.copy_x(t);
+        assert_eq!(v.0, 0x041f);
+        let mut v = VramAddr(0);
+        v.copy_y(t);
+        assert_eq!(v.0, 0x7be0);
+    }
+
+    #[test]
+    fn fetch_addresses() {
+        // Nametable 3, coarse X 29, coarse Y 17, fine Y 5
+        let v = VramAddr(0x5e3d);
+        assert_eq!(v.addr(), 0x1e3d);
+        assert_eq!(v.tile_addr(), 0x2e3d);
+        assert_eq!(v.attribute_addr(), 0x2fe7);
     }
 }
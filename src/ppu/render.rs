@@ -0,0 +1,307 @@
+use super::palette::Pixel;
+use super::regs::Status;
+use super::{palette_index, Ppu, DOTS_PER_SCANLINE, HEIGHT, SCANLINES_PER_FRAME, WIDTH};
+
+const VBLANK_SCANLINE: u16 = 241;
+const PRERENDER_SCANLINE: u16 = 261;
+
+/// Background fetch latches and shift registers
+#[derive(Default)]
+pub(super) struct Background {
+    /// Nametable byte of the next tile
+    nt: u8,
+    /// Palette number of the next tile
+    at: u8,
+    /// Pattern bitplanes of the next tile
+    pt_lo: u8,
+    pt_hi: u8,
+    /// Pattern shift registers, the high byte is the tile being drawn
+    shift_lo: u16,
+    shift_hi: u16,
+    /// Palette shift registers, the attribute bits expanded per pixel
+    at_lo: u16,
+    at_hi: u16,
+}
+
+impl Background {
+    fn shift(&mut self) {
+        self.shift_lo <<= 1;
+        self.shift_hi <<= 1;
+        self.at_lo <<= 1;
+        self.at_hi <<= 1;
+    }
+
+    // Move the fetched tile into the low byte of the shifters
+    fn reload(&mut self) {
+        self.shift_lo = (self.shift_lo & 0xff00) | u16::from(self.pt_lo);
+        self.shift_hi = (self.shift_hi & 0xff00) | u16::from(self.pt_hi);
+        self.at_lo = (self.at_lo & 0xff00) | if self.at & 0x01 != 0 { 0xff } else { 0 };
+        self.at_hi = (self.at_hi & 0xff00) | if self.at & 0x02 != 0 { 0xff } else { 0 };
+    }
+
+    // 4 bit palette index of the pixel fine_x dots into the current tile
+    fn pixel(&self, fine_x: u8) -> u8 {
+        let bit = 15 - fine_x;
+        let pix = (((self.shift_hi >> bit) & 1) << 1) | ((self.shift_lo >> bit) & 1);
+        let pal = (((self.at_hi >> bit) & 1) << 1) | ((self.at_lo >> bit) & 1);
+        if pix == 0 {
+            0
+        } else {
+            ((pal << 2) | pix) as u8
+        }
+    }
+}
+
+impl Ppu {
+    // Advance one dot
+    pub fn tick(&mut self) {
+        let visible = self.scanline < HEIGHT as u16;
+        let prerender = self.scanline == PRERENDER_SCANLINE;
+
+        if self.rendering() {
+            self.fetch();
+        }
+        if visible && (1..=WIDTH as u16).contains(&self.dot) {
+            self.output_pixel();
+        }
+
+        if self.scanline == VBLANK_SCANLINE && self.dot == 1 {
+            self.stat.insert(Status::V);
+            if self.ctrl.nmi() {
+                self.nmi = true;
+            }
+            self.frame_ready = true;
+            self.decay_latch();
+        } else if prerender && self.dot == 1 {
+            self.stat.remove(Status::V | Status::S | Status::O);
+        }
+
+        self.dot += 1;
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline = (self.scanline + 1) % SCANLINES_PER_FRAME;
+            if self.scanline == 0 {
+                // Odd frames skip the idle first dot while rendering. The
+                // pre-render line still gets its last dummy fetch, which
+                // mappers counting fetches rely on
+                if self.odd_frame && self.mask.rendering() {
+                    self.dot = 1;
+                }
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    // Background fetches for the current dot. Each tile takes 8 dots: the
+    // nametable byte, attribute byte and both pattern bitplanes, two dots
+    // apiece. Dots 1-256 fetch tiles 2-33 of the line, 321-336 prefetch the
+    // first two tiles of the next one
+    fn fetch(&mut self) {
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.bg.shift();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.bg.reload();
+                    self.bg.nt = self.read_nametable(self.v.tile_addr());
+                }
+                2 => {
+                    let at = self.read_nametable(self.v.attribute_addr());
+                    // Each attribute byte covers 4x4 tiles, two bits per 2x2 quadrant
+                    let shift = ((self.v.coarse_y() & 0x02) << 1) | (self.v.coarse_x() & 0x02);
+                    self.bg.at = (at >> shift) & 0x03;
+                }
+                4 => self.bg.pt_lo = self.read_pattern(self.pattern_addr()),
+                6 => self.bg.pt_hi = self.read_pattern(self.pattern_addr() + 8),
+                7 => self.v.increment_x(),
+                _ => {}
+            }
+        }
+
+        // Sprite fetches for the next line, 8 dots per slot with the
+        // pattern bytes in the second half. OAMADDR is held at 0
+        if (257..=320).contains(&dot) {
+            self.oam_addr = 0;
+            let slot = ((dot - 257) / 8) as usize;
+            match (dot - 257) % 8 {
+                4 => self.fetch_sprite(slot, false),
+                6 => self.fetch_sprite(slot, true),
+                _ => {}
+            }
+        }
+
+        match dot {
+            256 => self.v.increment_y(),
+            257 => {
+                self.v.copy_x(self.t);
+                if self.scanline == PRERENDER_SCANLINE {
+                    self.clear_sprites();
+                } else {
+                    self.evaluate_sprites();
+                }
+            }
+            280..=304 if self.scanline == PRERENDER_SCANLINE => self.v.copy_y(self.t),
+            // Unused nametable fetches at the end of the line
+            338 | 340 => {
+                self.read_nametable(self.v.tile_addr());
+            }
+            _ => {}
+        }
+    }
+
+    // Rendering is enabled and the PPU is on a line it fetches for
+    pub(super) fn rendering(&self) -> bool {
+        self.mask.rendering()
+            && (self.scanline < HEIGHT as u16 || self.scanline == PRERENDER_SCANLINE)
+    }
+
+    fn output_pixel(&mut self) {
+        let x = (self.dot - 1) as usize;
+        let y = self.scanline as usize;
+
+        let show_bg = self.mask.show_background() && (x >= 8 || self.mask.background_left());
+        let show_sprites = self.mask.show_sprites() && (x >= 8 || self.mask.sprites_left());
+        let bg = if show_bg { self.bg.pixel(self.x) } else { 0 };
+        let sprite = if show_sprites {
+            self.sprite_pixel(x as u8)
+        } else {
+            None
+        };
+
+        let color = match sprite {
+            None => bg,
+            Some(sprite) if bg == 0 => sprite.color,
+            Some(sprite) => {
+                // Opaque on opaque, never at x=255
+                if sprite.zero && x != WIDTH - 1 {
+                    self.stat.insert(Status::S);
+                }
+                if sprite.behind {
+                    bg
+                } else {
+                    sprite.color
+                }
+            }
+        };
+
+        let index = self.read_palette(color) & self.mask.greyscale();
+        self.frame[y * WIDTH + x] = Pixel::from(index) | self.mask.emphasis();
+    }
+
+    fn read_nametable(&self, addr: u16) -> u8 {
+        let val = {
+            let mut mapper = self.mapper.borrow_mut();
+            mapper.ppu_address(addr);
+            mapper.nametable_read(addr)
+        };
+        val.unwrap_or_else(|| self.vram[self.mirror(addr) as usize])
+    }
+
+    pub(super) fn read_pattern(&self, addr: u16) -> u8 {
+        let mut mapper = self.mapper.borrow_mut();
+        mapper.ppu_address(addr);
+        mapper.ppu_read(addr)
+    }
+
+    fn read_palette(&self, index: u8) -> u8 {
+        self.palette_ram[palette_index(u16::from(index))]
+    }
+
+    fn pattern_addr(&self) -> u16 {
+        self.ctrl.background_patterntable_address() as u16
+            + u16::from(self.bg.nt) * 16
+            + self.v.fine_y()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{test_image, Cartridge};
+    use crate::mapper;
+
+    const BACKDROP: Pixel = 0x0f;
+    const TILE: Pixel = 0x30;
+
+    // PPU on a horizontally mirrored NROM board. Tile 1 is solid color 1,
+    // the backdrop and color 1 of palettes 0 and 1 are set, and tile 1 is
+    // at the top left of the first nametable
+    fn setup() -> Ppu {
+        let mut rom = test_image(&[]);
+        let chr = 16 + 0x4000;
+        rom[chr + 0x10..chr + 0x18].fill(0xff);
+        let mut ppu = Ppu::new(mapper::new(Cartridge::new(&rom).unwrap()).unwrap());
+        ppu.write_address(0x3f);
+        ppu.write_address(0x00);
+        for color in [BACKDROP as u8, TILE as u8, 0, 0, 0, 0x16] {
+            ppu.write_vram(color);
+        }
+        ppu.write_address(0x20);
+        ppu.write_address(0x00);
+        ppu.write_vram(0x01);
+        ppu
+    }
+
+    // Scroll to X, then render two frames, the first has no pre-render line
+    // to set up v
+    fn render(ppu: &mut Ppu, scroll_x: u8) -> Vec<Pixel> {
+        ppu.write_ctrl(0);
+        ppu.write_scroll(scroll_x);
+        ppu.write_scroll(0);
+        for _ in 0..2 {
+            while !ppu.poll_frame() {
+                ppu.tick();
+            }
+        }
+        ppu.frame().to_vec()
+    }
+
+    #[test]
+    fn background_tile() {
+        let mut ppu = setup();
+        ppu.write_mask(0x0a);
+        let frame = render(&mut ppu, 0);
+        for y in 0..8 {
+            assert_eq!(frame[y * WIDTH..y * WIDTH + 8], [TILE; 8]);
+            assert_eq!(frame[y * WIDTH + 8], BACKDROP);
+        }
+        assert_eq!(frame[8 * WIDTH], BACKDROP);
+    }
+
+    #[test]
+    fn attributes() {
+        let mut ppu = setup();
+        ppu.write_address(0x23);
+        ppu.write_address(0xc0);
+        ppu.write_vram(0x01);
+        ppu.write_mask(0x0a);
+        let frame = render(&mut ppu, 0);
+        assert_eq!(frame[0], 0x16);
+    }
+
+    #[test]
+    fn left_column_hidden() {
+        let mut ppu = setup();
+        ppu.write_mask(0x08);
+        let frame = render(&mut ppu, 0);
+        assert_eq!(frame[..8], [BACKDROP; 8]);
+    }
+
+    #[test]
+    fn horizontal_scroll() {
+        // Fine X moves the tile 3 pixels left
+        let mut ppu = setup();
+        ppu.write_mask(0x0a);
+        let frame = render(&mut ppu, 3);
+        assert_eq!(frame[..6], [TILE, TILE, TILE, TILE, TILE, BACKDROP]);
+
+        // Coarse X moves it off the left edge, and back in from the right
+        // edge of the next nametable, a mirror of the first
+        let mut ppu = setup();
+        ppu.write_mask(0x0a);
+        let frame = render(&mut ppu, 8);
+        assert_eq!(frame[0], BACKDROP);
+        assert_eq!(frame[WIDTH - 9], BACKDROP);
+        assert_eq!(frame[WIDTH - 8..WIDTH], [TILE; 8]);
+    }
+}
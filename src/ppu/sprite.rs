@@ -0,0 +1,272 @@
+use super::regs::Status;
+use super::Ppu;
+
+/// Sprites per scanline
+const LINE_SPRITES: usize = 8;
+/// Sprite attribute bits
+const ATTR_PALETTE: u8 = 0x03;
+const ATTR_BEHIND: u8 = 0x20;
+const ATTR_FLIP_X: u8 = 0x40;
+const ATTR_FLIP_Y: u8 = 0x80;
+
+/// Sprite evaluation and output state
+#[derive(Default)]
+pub(super) struct Sprites {
+    /// Secondary OAM, the sprites found for the next line
+    secondary: [u8; LINE_SPRITES * 4],
+    /// Sprites in secondary OAM
+    count: usize,
+    /// Secondary OAM starts with sprite 0
+    zero_next: bool,
+    /// The first sprite of the line being drawn is sprite 0
+    zero: bool,
+    /// Pattern bitplanes, flipped so the leftmost pixel is bit 7
+    pt_lo: [u8; LINE_SPRITES],
+    pt_hi: [u8; LINE_SPRITES],
+    /// Attribute bytes
+    attr: [u8; LINE_SPRITES],
+    /// Left edges
+    x: [u8; LINE_SPRITES],
+}
+
+/// Opaque sprite pixel at a screen position
+pub(super) struct SpritePixel {
+    /// Palette index, $10-$1F
+    pub color: u8,
+    /// Drawn behind an opaque background pixel
+    pub behind: bool,
+    /// Comes from sprite 0
+    pub zero: bool,
+}
+
+impl Ppu {
+    // Find the sprites on the next line. The PPU does this over dots 65-256,
+    // doing it in one go is close enough since secondary OAM isn't visible
+    // until the fetches at 257
+    pub(super) fn evaluate_sprites(&mut self) {
+        let height = self.ctrl.sprite_height();
+        let line = self.scanline;
+        let in_range = |y: u8| line.wrapping_sub(u16::from(y)) < height;
+
+        let sprites = &mut self.sprites;
+        sprites.count = 0;
+        sprites.zero_next = false;
+
+        let mut n = 0;
+        while n < 64 && sprites.count < LINE_SPRITES {
+            let entry = &self.oam_data[n * 4..n * 4 + 4];
+            if in_range(entry[0]) {
+                let slot = sprites.count * 4;
+                sprites.secondary[slot..slot + 4].copy_from_slice(entry);
+                sprites.zero_next |= n == 0;
+                sprites.count += 1;
+            }
+            n += 1;
+        }
+
+        // With secondary OAM full the PPU keeps scanning for a ninth sprite,
+        // but increments the byte offset along with the sprite index on a
+        // miss, so it compares tile numbers, attributes and X positions as Y
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam_data[n * 4 + m]) {
+                self.stat.insert(Status::O);
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 3;
+        }
+    }
+
+    // The pre-render line doesn't evaluate, its fetches load no sprites
+    pub(super) fn clear_sprites(&mut self) {
+        self.sprites.count = 0;
+        self.sprites.zero_next = false;
+    }
+
+    // Fetch one pattern bitplane for a sprite slot at dots 257-320. Empty
+    // slots still fetch tile $FF
+    pub(super) fn fetch_sprite(&mut self, slot: usize, hi: bool) {
+        let (y, tile, attr, x) = if slot < self.sprites.count {
+            let entry = &self.sprites.secondary[slot * 4..slot * 4 + 4];
+            (entry[0], entry[1], entry[2], entry[3])
+        } else {
+            (0xff, 0xff, 0xff, 0xff)
+        };
+
+        let height = self.ctrl.sprite_height();
+        let mut row = self.scanline.wrapping_sub(u16::from(y)) % height;
+        if attr & ATTR_FLIP_Y != 0 {
+            row = height - 1 - row;
+        }
+
+        // 8x16 sprites take the table from bit 0 of the tile number, the
+        // bottom half is the next tile
+        let (table, tile) = if height == 16 {
+            (
+                u16::from(tile & 1) * 0x1000,
+                u16::from(tile & 0xfe) + row / 8,
+            )
+        } else {
+            (self.ctrl.sprite_patterntable_address(), u16::from(tile))
+        };
+        let addr = table + tile * 16 + row % 8 + if hi { 8 } else { 0 };
+        let mut data = self.read_pattern(addr);
+
+        if slot >= self.sprites.count {
+            data = 0;
+        } else if attr & ATTR_FLIP_X != 0 {
+            data = data.reverse_bits();
+        }
+
+        let sprites = &mut self.sprites;
+        if hi {
+            sprites.pt_hi[slot] = data;
+        } else {
+            sprites.pt_lo[slot] = data;
+        }
+        sprites.attr[slot] = attr;
+        sprites.x[slot] = x;
+        if slot == 0 {
+            sprites.zero = sprites.zero_next;
+        }
+    }
+
+    // Front-most opaque sprite pixel at screen column x
+    pub(super) fn sprite_pixel(&self, x: u8) -> Option<SpritePixel> {
+        let sprites = &self.sprites;
+        (0..LINE_SPRITES).find_map(|i| {
+            let col = x.checked_sub(sprites.x[i]).filter(|&col| col < 8)?;
+            let bit = 7 - col;
+            let pix = (((sprites.pt_hi[i] >> bit) & 1) << 1) | ((sprites.pt_lo[i] >> bit) & 1);
+            if pix == 0 {
+                return None;
+            }
+            let attr = sprites.attr[i];
+            Some(SpritePixel {
+                color: 0x10 | ((attr & ATTR_PALETTE) << 2) | pix,
+                behind: attr & ATTR_BEHIND != 0,
+                zero: i == 0 && sprites.zero,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{test_image, Cartridge};
+    use crate::mapper;
+    use crate::ppu::WIDTH;
+
+    // PPU on an NROM board with tile 1 solid color 1, placed at the top
+    // left of the background. Backdrop $0F, sprite color 1 is $16
+    fn setup() -> Ppu {
+        let mut rom = test_image(&[]);
+        let chr = 16 + 0x4000;
+        rom[chr + 0x10..chr + 0x18].fill(0xff);
+        let mut ppu = Ppu::new(mapper::new(Cartridge::new(&rom).unwrap()).unwrap());
+        ppu.write_address(0x3f);
+        ppu.write_address(0x00);
+        ppu.write_vram(0x0f);
+        ppu.write_vram(0x30);
+        ppu.write_address(0x3f);
+        ppu.write_address(0x11);
+        ppu.write_vram(0x16);
+        ppu.write_address(0x20);
+        ppu.write_address(0x00);
+        ppu.write_vram(0x01);
+        ppu.write_ctrl(0);
+        ppu.write_scroll(0);
+        ppu.write_scroll(0);
+        ppu
+    }
+
+    // Sprite 0 at a position, the rest of OAM off screen
+    fn place_sprite_zero(ppu: &mut Ppu, x: u8, y: u8) {
+        ppu.oam_data.fill(0xff);
+        ppu.oam_data[..4].copy_from_slice(&[y, 0x01, 0x00, x]);
+    }
+
+    // Run to the end of the second frame's visible lines
+    fn render(ppu: &mut Ppu) {
+        for _ in 0..2 {
+            while !ppu.poll_frame() {
+                ppu.tick();
+            }
+        }
+    }
+
+    #[test]
+    fn sprite_pixels() {
+        let mut ppu = setup();
+        place_sprite_zero(&mut ppu, 100, 50);
+        ppu.write_mask(0x1e);
+        render(&mut ppu);
+        // Sprites show up one line below their Y
+        let frame = ppu.frame();
+        assert_eq!(frame[50 * WIDTH + 100], 0x0f);
+        assert_eq!(frame[51 * WIDTH + 99], 0x0f);
+        assert_eq!(frame[51 * WIDTH + 100..51 * WIDTH + 108], [0x16; 8]);
+        assert_eq!(frame[58 * WIDTH + 100], 0x16);
+        assert_eq!(frame[59 * WIDTH + 100], 0x0f);
+    }
+
+    #[test]
+    fn sprite_zero_hit() {
+        let hit = |x: u8, mask: u8| {
+            let mut ppu = setup();
+            place_sprite_zero(&mut ppu, x, 0);
+            ppu.write_mask(mask);
+            render(&mut ppu);
+            ppu.stat.contains(Status::S)
+        };
+        assert!(hit(0, 0x1e));
+        assert!(hit(7, 0x1e));
+        // Past the background tile
+        assert!(!hit(8, 0x1e));
+        // Hidden in the left column
+        assert!(!hit(0, 0x18));
+        // Needs both layers on
+        assert!(!hit(0, 0x16));
+    }
+
+    #[test]
+    fn hit_flag_cleared_on_prerender() {
+        let mut ppu = setup();
+        place_sprite_zero(&mut ppu, 0, 0);
+        ppu.write_mask(0x1e);
+        render(&mut ppu);
+        ppu.write_mask(0);
+        while ppu.position() != (261, 2) {
+            ppu.tick();
+        }
+        assert!(!ppu.stat.contains(Status::S));
+    }
+
+    #[test]
+    fn overflow() {
+        // Sprite evaluation for line 20 with the given OAM
+        let overflow = |oam: &[[u8; 4]]| {
+            let mut ppu = setup();
+            for (i, entry) in oam.iter().enumerate() {
+                ppu.oam_data[i * 4..i * 4 + 4].copy_from_slice(entry);
+            }
+            ppu.scanline = 20;
+            ppu.evaluate_sprites();
+            (ppu.sprites.count, ppu.stat.contains(Status::O))
+        };
+        assert_eq!(overflow(&[[16, 0, 0, 0]; 8]), (8, false));
+        assert_eq!(overflow(&[[16, 0, 0, 0]; 9]), (8, true));
+
+        // After 8 hits the PPU looks at byte 1 of the next sprite, byte 2 of
+        // the one after and so on
+        let mut oam = [[16, 0, 0, 0]; 11];
+        oam[8] = [0xff, 0, 0, 0];
+        oam[9] = [0xff, 0, 0, 0];
+        oam[10] = [0xff, 0, 16, 0];
+        assert_eq!(overflow(&oam), (8, true));
+        oam[10] = [0xff, 16, 0, 0];
+        assert_eq!(overflow(&oam), (8, false));
+    }
+}
@@ -0,0 +1,23 @@
+use std::{fs, io::BufReader, path::PathBuf};
+
+use nes::cpu::nestest;
+
+// Runs nestest.nes against nestest.log from tests/roms, or the directory in
+// NESTEST_DIR. The ROM isn't redistributable, so this only runs on request:
+// cargo test -- --ignored
+#[test]
+#[ignore = "needs nestest.nes and nestest.log in tests/roms or NESTEST_DIR"]
+fn nestest_matches_log() {
+    let dir = std::env::var_os("NESTEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms"));
+    let rom = fs::read(dir.join("nestest.nes")).expect("missing nestest.nes");
+    let log = fs::File::open(dir.join("nestest.log")).expect("missing nestest.log");
+
+    if let Some(div) = nestest::run(&rom, BufReader::new(log)).unwrap() {
+        panic!(
+            "diverged at line {}\nexpected: {}\nactual:   {}",
+            div.line, div.expected, div.actual
+        );
+    }
+}